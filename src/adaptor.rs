@@ -1,11 +1,29 @@
 use crate::display;
-use crate::errors::Result;
+use crate::errors::{Error, Result};
+use crate::hooks::{HookPoint, Hooks};
 use crate::migration::Migration;
 use crate::plan_builder::Step;
+use crate::schema::Schema;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
 
 mod postgres;
 mod sqlite;
 
+/// The SQL dialect an adaptor speaks, for checks that differ between databases.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+/// A database Movine can migrate.
+///
+/// Only the methods needed to run versioned migrations are required. The others have default
+/// implementations, so that adaptors written against older versions keep working: the ones that
+/// load records report that there are none, and the rest fail with [`Error::Unsupported`].
 pub trait DbAdaptor {
     fn init_up_sql(&self) -> &'static str;
     fn init_down_sql(&self) -> &'static str;
@@ -13,25 +31,169 @@ pub trait DbAdaptor {
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()>;
     fn run_down_migration(&mut self, migration: &Migration) -> Result<()>;
 
-    fn run_migration_plan(&mut self, plan: &[(Step, &Migration)]) -> Result<()> {
+    /// The dialect used by `lint`. Defaults to Postgres.
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    /// Loads the repeatable migrations recorded in `movine_migrations`, with the SQL they were
+    /// last applied with as their up migration.
+    fn load_repeatable_migrations(&mut self) -> Result<Vec<Migration>> {
+        Ok(Vec::new())
+    }
+
+    /// Runs a repeatable migration and records it, replacing any earlier record of it.
+    fn run_repeatable_migration(&mut self, _migration: &Migration) -> Result<()> {
+        Err(Error::Unsupported("repeatable migrations"))
+    }
+
+    /// Loads the seeds recorded in `movine_migrations`.
+    fn load_seeds(&mut self) -> Result<Vec<Migration>> {
+        Ok(Vec::new())
+    }
+
+    /// Runs a seed and records it, replacing any earlier record of it.
+    fn run_seed(&mut self, _seed: &Migration) -> Result<()> {
+        Err(Error::Unsupported("seeds"))
+    }
+
+    /// Introspects the objects in the database, except Movine's own table.
+    fn dump_schema(&mut self) -> Result<Schema> {
+        Err(Error::Unsupported("dumping the schema"))
+    }
+
+    /// Runs `sql` (e.g. a hook) in a transaction of its own.
+    fn execute_sql(&mut self, _sql: &str) -> Result<()> {
+        Err(Error::Unsupported("running SQL hooks"))
+    }
+
+    /// Renames a migration in `movine_migrations`.
+    fn rename_migration(&mut self, _old: &str, _new: &str) -> Result<()> {
+        Err(Error::Unsupported("renaming migrations"))
+    }
+
+    /// Replaces the `movine_migrations` rows of the `old` migrations with a single row for
+    /// `new`, without running any SQL from the migrations themselves.
+    fn replace_migrations(&mut self, _old: &[String], _new: &Migration) -> Result<()> {
+        Err(Error::Unsupported("squashing migrations"))
+    }
+
+    fn run_migration_plan(
+        &mut self,
+        plan: &[(Step, &Migration)],
+        options: &RunOptions,
+    ) -> Result<()> {
+        if !plan.is_empty() {
+            options
+                .hooks
+                .run(self, HookPoint::BeforeAll, None, options.quiet)?;
+        }
         for (step, migration) in plan {
-            display::print_step(&(*step, migration));
-            match step {
-                Step::Up => {
-                    self.run_up_migration(migration)?;
-                }
-                Step::Down => {
-                    if migration.is_reversable() {
-                        self.run_down_migration(migration)?;
+            if !options.quiet {
+                display::print_step(&(*step, migration));
+            }
+            let current = Some((*step, *migration));
+            options
+                .hooks
+                .run(self, HookPoint::BeforeEach, current, options.quiet)?;
+            let mut attempt = 1;
+            loop {
+                let error = match run_step(self, *step, migration) {
+                    Ok(()) => break,
+                    Err(e) => e,
+                };
+                match options.retry {
+                    Some(retry) if error.is_retryable() && attempt <= retry.retries => {
+                        let delay = retry.delay(attempt);
+                        display::print_retry(migration, attempt, delay, &error);
+                        thread::sleep(delay);
+                        attempt += 1;
+                    }
+                    _ if error.is_timeout(migration) => {
+                        return Err(Error::MigrationTimeout {
+                            migration: migration.name.to_owned(),
+                            error: Box::new(error),
+                        });
                     }
+                    _ => match error {
+                        Error::MigrationFailed { .. } => return Err(error),
+                        _ => {
+                            return Err(Error::MigrationFailed {
+                                migration: migration.name.to_owned(),
+                                step: *step,
+                                location: None,
+                                error: Box::new(error),
+                            });
+                        }
+                    },
                 }
             }
+            options
+                .hooks
+                .run(self, HookPoint::AfterEach, current, options.quiet)?;
+        }
+        if !plan.is_empty() {
+            options
+                .hooks
+                .run(self, HookPoint::AfterAll, None, options.quiet)?;
         }
         Ok(())
     }
 }
 
+fn run_step<T: DbAdaptor + ?Sized>(
+    adaptor: &mut T,
+    step: Step,
+    migration: &Migration,
+) -> Result<()> {
+    match step {
+        Step::Up => adaptor.run_up_migration(migration),
+        Step::Down if migration.is_reversable() => adaptor.run_down_migration(migration),
+        Step::Down => Ok(()),
+        Step::Repeat => adaptor.run_repeatable_migration(migration),
+        Step::Seed => adaptor.run_seed(migration),
+    }
+}
+
+/// Options controlling how a migration plan is run.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Retry steps that fail on a lock timeout, deadlock or serialization failure.
+    pub retry: Option<RetryPolicy>,
+    /// Don't print each step as it runs.
+    pub quiet: bool,
+    /// SQL and commands to run around the plan and each of its steps.
+    pub hooks: Hooks,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How many times a failing step is retried before giving up.
+    pub retries: u32,
+    /// Delay before the first retry. It doubles for every further retry, with jitter.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self.backoff * 2u32.pow((attempt - 1).min(16));
+        // Randomize between 50% and 100% of the delay so concurrent deploys don't retry in
+        // lockstep.
+        exponential.mul_f64(0.5 + random_fraction() / 2.0)
+    }
+}
+
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    (hasher.finish() % 1000) as f64 / 1000.0
+}
+
 impl<T: DbAdaptor + ?Sized> DbAdaptor for &'_ mut T {
+    fn dialect(&self) -> Dialect {
+        (**self).dialect()
+    }
+
     fn init_up_sql(&self) -> &'static str {
         (**self).init_up_sql()
     }
@@ -44,6 +206,10 @@ impl<T: DbAdaptor + ?Sized> DbAdaptor for &'_ mut T {
         (**self).load_migrations()
     }
 
+    fn load_repeatable_migrations(&mut self) -> Result<Vec<Migration>> {
+        (**self).load_repeatable_migrations()
+    }
+
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         (**self).run_up_migration(migration)
     }
@@ -52,12 +218,48 @@ impl<T: DbAdaptor + ?Sized> DbAdaptor for &'_ mut T {
         (**self).run_down_migration(migration)
     }
 
-    fn run_migration_plan(&mut self, plan: &[(Step, &Migration)]) -> Result<()> {
-        (**self).run_migration_plan(plan)
+    fn run_repeatable_migration(&mut self, migration: &Migration) -> Result<()> {
+        (**self).run_repeatable_migration(migration)
+    }
+
+    fn load_seeds(&mut self) -> Result<Vec<Migration>> {
+        (**self).load_seeds()
+    }
+
+    fn run_seed(&mut self, seed: &Migration) -> Result<()> {
+        (**self).run_seed(seed)
+    }
+
+    fn dump_schema(&mut self) -> Result<Schema> {
+        (**self).dump_schema()
+    }
+
+    fn replace_migrations(&mut self, old: &[String], new: &Migration) -> Result<()> {
+        (**self).replace_migrations(old, new)
+    }
+
+    fn execute_sql(&mut self, sql: &str) -> Result<()> {
+        (**self).execute_sql(sql)
+    }
+
+    fn rename_migration(&mut self, old: &str, new: &str) -> Result<()> {
+        (**self).rename_migration(old, new)
+    }
+
+    fn run_migration_plan(
+        &mut self,
+        plan: &[(Step, &Migration)],
+        options: &RunOptions,
+    ) -> Result<()> {
+        (**self).run_migration_plan(plan, options)
     }
 }
 
 impl<T: DbAdaptor + ?Sized> DbAdaptor for Box<T> {
+    fn dialect(&self) -> Dialect {
+        (**self).dialect()
+    }
+
     fn init_up_sql(&self) -> &'static str {
         (**self).init_up_sql()
     }
@@ -70,6 +272,10 @@ impl<T: DbAdaptor + ?Sized> DbAdaptor for Box<T> {
         (**self).load_migrations()
     }
 
+    fn load_repeatable_migrations(&mut self) -> Result<Vec<Migration>> {
+        (**self).load_repeatable_migrations()
+    }
+
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         (**self).run_up_migration(migration)
     }
@@ -78,7 +284,180 @@ impl<T: DbAdaptor + ?Sized> DbAdaptor for Box<T> {
         (**self).run_down_migration(migration)
     }
 
-    fn run_migration_plan(&mut self, plan: &[(Step, &Migration)]) -> Result<()> {
-        (**self).run_migration_plan(plan)
+    fn run_repeatable_migration(&mut self, migration: &Migration) -> Result<()> {
+        (**self).run_repeatable_migration(migration)
+    }
+
+    fn load_seeds(&mut self) -> Result<Vec<Migration>> {
+        (**self).load_seeds()
+    }
+
+    fn run_seed(&mut self, seed: &Migration) -> Result<()> {
+        (**self).run_seed(seed)
+    }
+
+    fn dump_schema(&mut self) -> Result<Schema> {
+        (**self).dump_schema()
+    }
+
+    fn replace_migrations(&mut self, old: &[String], new: &Migration) -> Result<()> {
+        (**self).replace_migrations(old, new)
+    }
+
+    fn execute_sql(&mut self, sql: &str) -> Result<()> {
+        (**self).execute_sql(sql)
+    }
+
+    fn rename_migration(&mut self, old: &str, new: &str) -> Result<()> {
+        (**self).rename_migration(old, new)
+    }
+
+    fn run_migration_plan(
+        &mut self,
+        plan: &[(Step, &Migration)],
+        options: &RunOptions,
+    ) -> Result<()> {
+        (**self).run_migration_plan(plan, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::MigrationBuilder;
+    use crate::schema::ObjectKind;
+    use rusqlite::Connection;
+
+    #[test]
+    /// The delay should double with every attempt, jittered down to no less than half of it.
+    fn test_retry_delay() {
+        let retry = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        };
+        for _ in 0..20 {
+            let first = retry.delay(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = retry.delay(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    /// A step that finds the database locked should be retried until the lock is released, and
+    /// fail right away without a retry policy.
+    fn test_retry_locked_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let migration = MigrationBuilder::new()
+            .compound_name("0001_test")
+            .up_sql("CREATE TABLE t (x int);")
+            .build()
+            .unwrap();
+        let plan = [(Step::Up, &migration)];
+        let mut conn = Connection::open(&path).unwrap();
+        conn.execute_batch(conn.init_up_sql()).unwrap();
+        conn.busy_timeout(Duration::from_millis(0)).unwrap();
+
+        let locker = Connection::open(&path).unwrap();
+        locker.execute_batch("BEGIN EXCLUSIVE;").unwrap();
+        let options = RunOptions {
+            quiet: true,
+            ..RunOptions::default()
+        };
+        let result = conn.run_migration_plan(&plan, &options);
+        assert!(matches!(result, Err(Error::MigrationFailed { .. })));
+
+        let unlock = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            locker.execute_batch("COMMIT;").unwrap();
+        });
+        let options = RunOptions {
+            retry: Some(RetryPolicy {
+                retries: 6,
+                backoff: Duration::from_millis(100),
+            }),
+            ..options
+        };
+        conn.run_migration_plan(&plan, &options).unwrap();
+        unlock.join().unwrap();
+        assert_eq!(conn.load_migrations().unwrap().len(), 1);
+    }
+
+    #[test]
+    /// The schema dump should list the database's objects, but not Movine's own table.
+    fn test_dump_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(conn.init_up_sql()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            CREATE INDEX users_name ON users (name);
+            CREATE VIEW names AS SELECT name FROM users;
+            CREATE TRIGGER users_insert AFTER INSERT ON users BEGIN SELECT 1; END;",
+        )
+        .unwrap();
+
+        let schema = conn.dump_schema().unwrap();
+        let objects: Vec<_> = schema
+            .objects
+            .iter()
+            .map(|object| (object.kind, object.name.as_str()))
+            .collect();
+        assert_eq!(
+            objects,
+            vec![
+                (ObjectKind::Table, "users"),
+                (ObjectKind::Index, "users_name"),
+                (ObjectKind::View, "names"),
+                (ObjectKind::Trigger, "users_insert"),
+            ]
+        );
+        assert_eq!(
+            schema.objects[1].definition,
+            "CREATE INDEX users_name ON users (name);"
+        );
+    }
+
+    /// An adaptor that only implements the required methods.
+    struct MinimalAdaptor;
+
+    impl DbAdaptor for MinimalAdaptor {
+        fn init_up_sql(&self) -> &'static str {
+            ""
+        }
+
+        fn init_down_sql(&self) -> &'static str {
+            ""
+        }
+
+        fn load_migrations(&mut self) -> Result<Vec<Migration>> {
+            Ok(Vec::new())
+        }
+
+        fn run_up_migration(&mut self, _migration: &Migration) -> Result<()> {
+            Ok(())
+        }
+
+        fn run_down_migration(&mut self, _migration: &Migration) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Adaptors that don't implement the optional methods should have nothing recorded and
+    /// report the rest as unsupported.
+    fn test_default_methods() {
+        let mut adaptor = MinimalAdaptor;
+        assert_eq!(adaptor.dialect(), Dialect::Postgres);
+        assert!(adaptor.load_repeatable_migrations().unwrap().is_empty());
+        assert!(adaptor.load_seeds().unwrap().is_empty());
+        assert!(matches!(
+            adaptor.dump_schema(),
+            Err(Error::Unsupported("dumping the schema"))
+        ));
+        assert!(matches!(
+            adaptor.rename_migration("a", "b"),
+            Err(Error::Unsupported(_))
+        ));
     }
 }
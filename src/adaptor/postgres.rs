@@ -1,8 +1,16 @@
-use crate::adaptor::DbAdaptor;
+use crate::adaptor::{DbAdaptor, Dialect};
 use crate::errors::{Error, Result};
 use crate::migration::{Migration, MigrationBuilder};
+use crate::plan_builder::Step;
+use crate::schema::{self, ObjectKind, Schema, SchemaObject};
+use crate::sql::execute_statements;
+use postgres::{GenericClient, Transaction};
 
 impl DbAdaptor for postgres::Client {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
     fn init_up_sql(&self) -> &'static str {
         INIT_UP_SQL
     }
@@ -12,20 +20,20 @@ impl DbAdaptor for postgres::Client {
     }
 
     fn load_migrations(&mut self) -> Result<Vec<Migration>> {
+        let sql = if has_kind_column(self)? {
+            LOAD_VERSIONED_MIGRATIONS
+        } else {
+            LOAD_MIGRATIONS
+        };
         let mut migrations = Vec::new();
-        let sql = "
-            SELECT name, hash, down_sql
-            FROM movine_migrations
-            ORDER BY created_at DESC;
-        ";
-        let rows = self.query(sql, &[])?;
-        for row in &rows {
+        for row in &self.query(sql, &[])? {
             let name: String = row.get(0);
             let hash: String = row.get(1);
             let down_sql: String = row.get(2);
             let migration = MigrationBuilder::new()
                 .compound_name(&name)
                 .hash(&hash)
+                .lenient()
                 .down_sql(&down_sql)
                 .build()?;
             migrations.push(migration);
@@ -33,15 +41,32 @@ impl DbAdaptor for postgres::Client {
         Ok(migrations)
     }
 
+    fn load_repeatable_migrations(&mut self) -> Result<Vec<Migration>> {
+        load_tracked(self, REPEATABLE)
+    }
+
+    fn load_seeds(&mut self) -> Result<Vec<Migration>> {
+        load_tracked(self, SEED)
+    }
+
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         let name = &migration.name;
-        let hash = migration.hash.as_ref().ok_or(Error::BadMigration)?;
-        let up_sql = migration.up_sql.as_ref().ok_or(Error::BadMigration)?;
+        let hash = migration
+            .hash
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+        let up_sql = migration
+            .up_sql
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no up migration", name)))?;
         let empty_string = "".to_string();
         let down_sql = migration.down_sql.as_ref().unwrap_or(&empty_string);
 
         let mut transaction = self.transaction()?;
-        transaction.batch_execute(up_sql)?;
+        set_timeouts(&mut transaction, migration)?;
+        execute_statements(migration, Step::Up, up_sql, |statement| {
+            Ok(transaction.batch_execute(statement)?)
+        })?;
         transaction.execute(LOG_UP_MIGRATION, &[&name, &hash, &down_sql])?;
         transaction.commit()?;
         Ok(())
@@ -49,16 +74,281 @@ impl DbAdaptor for postgres::Client {
 
     fn run_down_migration(&mut self, migration: &Migration) -> Result<()> {
         let name = &migration.name;
-        let down_sql = migration.down_sql.as_ref().ok_or(Error::BadMigration)?;
+        let down_sql = migration
+            .down_sql
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no down migration", name)))?;
+
+        let mut transaction = self.transaction()?;
+        set_timeouts(&mut transaction, migration)?;
+        execute_statements(migration, Step::Down, down_sql, |statement| {
+            Ok(transaction.batch_execute(statement)?)
+        })?;
+        let log_down = log_down_sql(&mut transaction)?;
+        transaction.execute(log_down, &[&name])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn run_repeatable_migration(&mut self, migration: &Migration) -> Result<()> {
+        run_tracked(self, migration, Step::Repeat, REPEATABLE)
+    }
+
+    fn run_seed(&mut self, seed: &Migration) -> Result<()> {
+        run_tracked(self, seed, Step::Seed, SEED)
+    }
+
+    fn execute_sql(&mut self, sql: &str) -> Result<()> {
+        let mut transaction = self.transaction()?;
+        transaction.batch_execute(sql)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn rename_migration(&mut self, old: &str, new: &str) -> Result<()> {
+        let rename = if has_kind_column(self)? {
+            RENAME_VERSIONED_MIGRATION
+        } else {
+            RENAME_MIGRATION
+        };
+        self.execute(rename, &[&new, &old])?;
+        Ok(())
+    }
+
+    fn replace_migrations(&mut self, old: &[String], new: &Migration) -> Result<()> {
+        let name = &new.name;
+        let hash = new
+            .hash
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+        let down_sql = new.down_sql.as_deref().unwrap_or("");
 
         let mut transaction = self.transaction()?;
-        transaction.batch_execute(down_sql)?;
-        transaction.execute(LOG_DOWN_MIGRATION, &[&name])?;
+        let log_down = log_down_sql(&mut transaction)?;
+        for old_name in old.iter().chain(std::iter::once(name)) {
+            transaction.execute(log_down, &[old_name])?;
+        }
+        transaction.execute(LOG_UP_MIGRATION, &[name, hash, &down_sql])?;
         transaction.commit()?;
         Ok(())
     }
+
+    fn dump_schema(&mut self) -> Result<Schema> {
+        let mut objects = Vec::new();
+
+        for row in self.query(DUMP_ENUMS_SQL, &[])? {
+            let name: String = row.get(0);
+            let labels: String = row.get(1);
+            objects.push(SchemaObject {
+                definition: format!("CREATE TYPE {} AS ENUM ({});", name, labels),
+                kind: ObjectKind::Type,
+                name,
+            });
+        }
+
+        for table in self.query(DUMP_TABLES_SQL, &[])? {
+            let oid: u32 = table.get(0);
+            let name: String = table.get(1);
+            let columns = self
+                .query(DUMP_COLUMNS_SQL, &[&oid])?
+                .iter()
+                .map(|column| {
+                    let name: String = column.get(0);
+                    let data_type: String = column.get(1);
+                    let not_null: bool = column.get(2);
+                    let default: Option<String> = column.get(3);
+                    let identity: String = column.get(4);
+                    let generated: String = column.get(5);
+
+                    let mut definition = format!("{} {}", name, data_type);
+                    match (default, generated.as_str()) {
+                        (Some(expr), "s") => {
+                            definition += &format!(" GENERATED ALWAYS AS ({}) STORED", expr)
+                        }
+                        (Some(expr), _) => definition += &format!(" DEFAULT {}", expr),
+                        (None, _) => {}
+                    }
+                    match identity.as_str() {
+                        "a" => definition += " GENERATED ALWAYS AS IDENTITY",
+                        "d" => definition += " GENERATED BY DEFAULT AS IDENTITY",
+                        _ => {}
+                    }
+                    if not_null {
+                        definition += " NOT NULL";
+                    }
+                    definition
+                })
+                .collect::<Vec<_>>();
+            let constraints = self
+                .query(DUMP_CONSTRAINTS_SQL, &[&oid])?
+                .iter()
+                .map(|constraint| {
+                    let name: String = constraint.get(0);
+                    let definition: String = constraint.get(1);
+                    format!("CONSTRAINT {} {}", name, definition)
+                })
+                .collect::<Vec<_>>();
+            objects.push(SchemaObject {
+                definition: schema::create_table(&name, &columns, &constraints),
+                kind: ObjectKind::Table,
+                name,
+            });
+        }
+
+        let simple_objects = [
+            (ObjectKind::Sequence, DUMP_SEQUENCES_SQL),
+            (ObjectKind::Index, DUMP_INDEXES_SQL),
+            (ObjectKind::View, DUMP_VIEWS_SQL),
+            (ObjectKind::Function, DUMP_FUNCTIONS_SQL),
+            (ObjectKind::Trigger, DUMP_TRIGGERS_SQL),
+        ];
+        for (kind, sql) in simple_objects.iter() {
+            for row in self.query(*sql, &[])? {
+                let name: String = row.get(0);
+                let definition: String = row.get(1);
+                objects.push(SchemaObject {
+                    kind: *kind,
+                    name,
+                    definition: format!("{};", definition.trim_end().trim_end_matches(';')),
+                });
+            }
+        }
+
+        Ok(Schema::new(objects))
+    }
+}
+
+/// Loads the rows of `movine_migrations` of the given `kind`, with the SQL they were last run
+/// with as their up migration.
+fn load_tracked(client: &mut postgres::Client, kind: &str) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    if !has_kind_column(client)? {
+        return Ok(migrations);
+    }
+    for row in &client.query(LOAD_TRACKED_MIGRATIONS, &[&kind])? {
+        let name: String = row.get(0);
+        let hash: String = row.get(1);
+        let sql: String = row.get(2);
+        let migration = MigrationBuilder::new()
+            .compound_name(&name)
+            .hash(&hash)
+            .lenient()
+            .up_sql(&sql)
+            .build()?;
+        migrations.push(migration);
+    }
+    Ok(migrations)
+}
+
+/// Runs the up SQL of a migration that isn't versioned and records it as `kind`, replacing any
+/// earlier record of it.
+fn run_tracked(
+    client: &mut postgres::Client,
+    migration: &Migration,
+    step: Step,
+    kind: &str,
+) -> Result<()> {
+    let name = &migration.name;
+    let hash = migration
+        .hash
+        .as_ref()
+        .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+    let sql = migration
+        .up_sql
+        .as_ref()
+        .ok_or_else(|| Error::BadMigration(format!("`{}` has no SQL", name)))?;
+
+    let mut transaction = client.transaction()?;
+    set_timeouts(&mut transaction, migration)?;
+    if !has_kind_column(&mut transaction)? {
+        transaction.batch_execute(ADD_KIND_COLUMN)?;
+    }
+    execute_statements(migration, step, sql, |statement| {
+        Ok(transaction.batch_execute(statement)?)
+    })?;
+    transaction.execute(FORGET_TRACKED_MIGRATION, &[&name, &kind])?;
+    transaction.execute(LOG_TRACKED_MIGRATION, &[&name, &hash, &sql, &kind])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Whether `movine_migrations` has the `kind` column, which is only added once a repeatable
+/// migration or seed is run. Until then every row is a versioned migration.
+fn has_kind_column<C: GenericClient>(client: &mut C) -> Result<bool> {
+    Ok(client.query_one(HAS_KIND_COLUMN, &[])?.get(0))
+}
+
+/// The query deleting the row of a versioned migration, which mustn't touch the rows of
+/// repeatable migrations or seeds with the same name.
+fn log_down_sql<C: GenericClient>(client: &mut C) -> Result<&'static str> {
+    Ok(if has_kind_column(client)? {
+        LOG_VERSIONED_DOWN_MIGRATION
+    } else {
+        LOG_DOWN_MIGRATION
+    })
+}
+
+/// Applies the migration's timeouts to the current transaction only.
+fn set_timeouts(transaction: &mut Transaction, migration: &Migration) -> Result<()> {
+    if let Some(timeout) = migration.lock_timeout {
+        let sql = format!("SET LOCAL lock_timeout = '{}ms';", timeout.as_millis());
+        transaction.batch_execute(&sql)?;
+    }
+    if let Some(timeout) = migration.statement_timeout {
+        let sql = format!("SET LOCAL statement_timeout = '{}ms';", timeout.as_millis());
+        transaction.batch_execute(&sql)?;
+    }
+    Ok(())
 }
 
+const LOAD_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+ORDER BY created_at DESC;
+";
+
+const LOAD_VERSIONED_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+WHERE kind = 'versioned'
+ORDER BY created_at DESC;
+";
+
+const REPEATABLE: &str = "repeatable";
+const SEED: &str = "seed";
+
+// The rows of repeatable migrations and seeds keep the SQL they were run with in `down_sql`.
+const LOAD_TRACKED_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+WHERE kind = $1
+ORDER BY name;
+";
+
+const HAS_KIND_COLUMN: &str = "\
+SELECT EXISTS (
+    SELECT 1
+    FROM information_schema.columns
+    WHERE table_schema = current_schema()
+        AND table_name = 'movine_migrations'
+        AND column_name = 'kind'
+);
+";
+
+const ADD_KIND_COLUMN: &str = "\
+ALTER TABLE movine_migrations ADD COLUMN kind TEXT NOT NULL DEFAULT 'versioned';
+";
+
+const LOG_TRACKED_MIGRATION: &str = "\
+INSERT INTO movine_migrations (name, hash, down_sql, kind)
+VALUES ($1, $2, $3, $4);
+";
+
+const FORGET_TRACKED_MIGRATION: &str = "\
+DELETE FROM movine_migrations
+WHERE name = $1 AND kind = $2;
+";
+
 pub const LOG_UP_MIGRATION: &str = "\
 INSERT INTO movine_migrations (name, hash, down_sql)
 VALUES ($1, $2, $3);
@@ -69,6 +359,119 @@ DELETE FROM movine_migrations
 WHERE name = $1;
 ";
 
+const LOG_VERSIONED_DOWN_MIGRATION: &str = "\
+DELETE FROM movine_migrations
+WHERE name = $1 AND kind = 'versioned';
+";
+
+// The dump queries only look at the current schema (usually `public`).
+const DUMP_ENUMS_SQL: &str = "\
+SELECT quote_ident(t.typname),
+    string_agg(quote_literal(e.enumlabel), ', ' ORDER BY e.enumsortorder)
+FROM pg_type t
+JOIN pg_enum e ON e.enumtypid = t.oid
+WHERE t.typnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+GROUP BY t.typname;
+";
+
+// Sequences behind identity columns are part of the column definition, and Movine's own
+// `movine_migrations_id_seq` is skipped like its table.
+const DUMP_SEQUENCES_SQL: &str = "\
+SELECT quote_ident(c.relname),
+    'CREATE SEQUENCE ' || quote_ident(c.relname) || ' AS ' || format_type(s.seqtypid, NULL)
+FROM pg_class c
+JOIN pg_sequence s ON s.seqrelid = c.oid
+WHERE c.relnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND NOT EXISTS (
+        SELECT 1 FROM pg_depend d
+        LEFT JOIN pg_class t ON t.oid = d.refobjid
+        WHERE d.objid = c.oid AND (d.deptype = 'i' OR t.relname = 'movine_migrations')
+    );
+";
+
+const DUMP_TABLES_SQL: &str = "\
+SELECT c.oid, quote_ident(c.relname)
+FROM pg_class c
+WHERE c.relnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND c.relkind IN ('r', 'p')
+    AND c.relname <> 'movine_migrations';
+";
+
+const DUMP_COLUMNS_SQL: &str = "\
+SELECT quote_ident(a.attname),
+    format_type(a.atttypid, a.atttypmod),
+    a.attnotnull,
+    pg_get_expr(d.adbin, d.adrelid),
+    a.attidentity::text,
+    a.attgenerated::text
+FROM pg_attribute a
+LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+WHERE a.attrelid = $1 AND a.attnum > 0 AND NOT a.attisdropped
+ORDER BY a.attnum;
+";
+
+const DUMP_CONSTRAINTS_SQL: &str = "\
+SELECT quote_ident(conname), pg_get_constraintdef(oid, true)
+FROM pg_constraint
+WHERE conrelid = $1 AND contype <> 'n'
+ORDER BY conname;
+";
+
+// Indexes backing a primary key, unique or exclusion constraint are part of the table.
+const DUMP_INDEXES_SQL: &str = "\
+SELECT quote_ident(i.relname), pg_get_indexdef(i.oid)
+FROM pg_index x
+JOIN pg_class i ON i.oid = x.indexrelid
+JOIN pg_class t ON t.oid = x.indrelid
+WHERE t.relnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND t.relname <> 'movine_migrations'
+    AND NOT EXISTS (
+        SELECT 1 FROM pg_constraint c
+        WHERE c.conindid = i.oid AND c.conrelid = t.oid AND c.contype IN ('p', 'u', 'x')
+    );
+";
+
+const DUMP_VIEWS_SQL: &str = "\
+SELECT quote_ident(relname),
+    CASE relkind WHEN 'm' THEN 'CREATE MATERIALIZED VIEW ' ELSE 'CREATE VIEW ' END
+        || quote_ident(relname) || ' AS' || chr(10) || pg_get_viewdef(oid, true)
+FROM pg_class
+WHERE relnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND relkind IN ('v', 'm');
+";
+
+// Functions that belong to an extension are created by the extension, not by migrations.
+const DUMP_FUNCTIONS_SQL: &str = "\
+SELECT p.oid::regprocedure::text, pg_get_functiondef(p.oid)
+FROM pg_proc p
+WHERE p.pronamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND p.prokind IN ('f', 'p')
+    AND NOT EXISTS (
+        SELECT 1 FROM pg_depend d WHERE d.objid = p.oid AND d.deptype = 'e'
+    );
+";
+
+const DUMP_TRIGGERS_SQL: &str = "\
+SELECT quote_ident(t.tgname) || ' ON ' || quote_ident(c.relname),
+    pg_get_triggerdef(t.oid, true)
+FROM pg_trigger t
+JOIN pg_class c ON c.oid = t.tgrelid
+WHERE c.relnamespace = (SELECT oid FROM pg_namespace WHERE nspname = current_schema())
+    AND NOT t.tgisinternal;
+";
+
+pub const RENAME_MIGRATION: &str = "\
+UPDATE movine_migrations
+SET name = $1, updated_at = now()
+WHERE name = $2;
+";
+
+const RENAME_VERSIONED_MIGRATION: &str = "\
+UPDATE movine_migrations
+SET name = $1, updated_at = now()
+WHERE name = $2 AND kind = 'versioned';
+";
+
 pub const INIT_UP_SQL: &str = "\
 CREATE TABLE movine_migrations (
     id SERIAL PRIMARY KEY,
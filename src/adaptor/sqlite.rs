@@ -1,9 +1,17 @@
-use crate::adaptor::DbAdaptor;
+use crate::adaptor::{DbAdaptor, Dialect};
 use crate::errors::{Error, Result};
 use crate::migration::{Migration, MigrationBuilder};
+use crate::plan_builder::Step;
+use crate::schema::{ObjectKind, Schema, SchemaObject};
+use crate::sql::execute_statements;
 use rusqlite::{params, Connection};
+use std::time::Duration;
 
 impl DbAdaptor for Connection {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
     fn init_up_sql(&self) -> &'static str {
         INIT_UP_SQL
     }
@@ -13,25 +21,20 @@ impl DbAdaptor for Connection {
     }
 
     fn load_migrations(&mut self) -> Result<Vec<Migration>> {
+        let sql = if has_kind_column(self)? {
+            LOAD_VERSIONED_MIGRATIONS
+        } else {
+            LOAD_MIGRATIONS
+        };
         let mut migrations = Vec::new();
-        let sql = "
-            SELECT name, hash, down_sql
-            FROM movine_migrations
-            ORDER BY created_at DESC;
-        ";
-        let mut stmt = self.prepare(sql)?;
-        let rows: std::result::Result<Vec<(String, String, String)>, _> = stmt
-            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
-            .collect();
-        let rows: Vec<(String, String, String)> = rows.unwrap();
-
-        for row in rows {
+        for row in load_rows(self, sql)? {
             let name: String = row.0;
             let hash: String = row.1;
             let down_sql: String = row.2;
             let migration = MigrationBuilder::new()
                 .compound_name(&name)
                 .hash(&hash)
+                .lenient()
                 .down_sql(&down_sql)
                 .build()?;
             migrations.push(migration);
@@ -39,32 +42,278 @@ impl DbAdaptor for Connection {
         Ok(migrations)
     }
 
+    fn load_repeatable_migrations(&mut self) -> Result<Vec<Migration>> {
+        load_tracked(self, REPEATABLE)
+    }
+
+    fn load_seeds(&mut self) -> Result<Vec<Migration>> {
+        load_tracked(self, SEED)
+    }
+
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         let name = &migration.name;
-        let hash = migration.hash.as_ref().ok_or(Error::BadMigration)?;
-        let up_sql = migration.up_sql.as_ref().ok_or(Error::BadMigration)?;
+        let hash = migration
+            .hash
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+        let up_sql = migration
+            .up_sql
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no up migration", name)))?;
         let empty_string = "".to_string();
         let down_sql = migration.down_sql.as_ref().unwrap_or(&empty_string);
 
+        with_timeouts(self, migration, |conn| {
+            let transaction = conn.transaction()?;
+            execute_statements(migration, Step::Up, up_sql, |statement| {
+                Ok(transaction.execute_batch(statement)?)
+            })?;
+            transaction.execute(LOG_UP_MIGRATION, &[&name, &hash, &down_sql])?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn run_down_migration(&mut self, migration: &Migration) -> Result<()> {
+        let name = &migration.name;
+        let down_sql = migration
+            .down_sql
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no down migration", name)))?;
+
+        with_timeouts(self, migration, |conn| {
+            let transaction = conn.transaction()?;
+            execute_statements(migration, Step::Down, down_sql, |statement| {
+                Ok(transaction.execute_batch(statement)?)
+            })?;
+            transaction.execute(log_down_sql(&transaction)?, &[&name])?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn run_repeatable_migration(&mut self, migration: &Migration) -> Result<()> {
+        run_tracked(self, migration, Step::Repeat, REPEATABLE)
+    }
+
+    fn run_seed(&mut self, seed: &Migration) -> Result<()> {
+        run_tracked(self, seed, Step::Seed, SEED)
+    }
+
+    fn execute_sql(&mut self, sql: &str) -> Result<()> {
         let transaction = self.transaction()?;
-        transaction.execute_batch(up_sql)?;
-        transaction.execute(LOG_UP_MIGRATION, &[&name, &hash, &down_sql])?;
+        transaction.execute_batch(sql)?;
         transaction.commit()?;
         Ok(())
     }
 
-    fn run_down_migration(&mut self, migration: &Migration) -> Result<()> {
-        let name = &migration.name;
-        let down_sql = migration.down_sql.as_ref().ok_or(Error::BadMigration)?;
+    fn rename_migration(&mut self, old: &str, new: &str) -> Result<()> {
+        let rename = if has_kind_column(self)? {
+            RENAME_VERSIONED_MIGRATION
+        } else {
+            RENAME_MIGRATION
+        };
+        self.execute(rename, params![new, old])?;
+        Ok(())
+    }
+
+    fn replace_migrations(&mut self, old: &[String], new: &Migration) -> Result<()> {
+        let name = &new.name;
+        let hash = new
+            .hash
+            .as_ref()
+            .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+        let down_sql = new.down_sql.as_deref().unwrap_or("");
 
         let transaction = self.transaction()?;
-        transaction.execute_batch(down_sql)?;
-        transaction.execute(LOG_DOWN_MIGRATION, &[&name])?;
+        let log_down = log_down_sql(&transaction)?;
+        for old_name in old.iter().chain(std::iter::once(name)) {
+            transaction.execute(log_down, &[old_name])?;
+        }
+        transaction.execute(LOG_UP_MIGRATION, params![name, hash, down_sql])?;
         transaction.commit()?;
         Ok(())
     }
+
+    fn dump_schema(&mut self) -> Result<Schema> {
+        let mut stmt = self.prepare(DUMP_SCHEMA_SQL)?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut objects = Vec::new();
+        for row in rows {
+            let (kind, name, sql) = row?;
+            let kind = match kind.as_str() {
+                "table" => ObjectKind::Table,
+                "index" => ObjectKind::Index,
+                "view" => ObjectKind::View,
+                "trigger" => ObjectKind::Trigger,
+                _ => continue,
+            };
+            objects.push(SchemaObject {
+                kind,
+                name,
+                definition: format!("{};", sql.trim_end()),
+            });
+        }
+        Ok(Schema::new(objects))
+    }
 }
 
+/// Loads the rows of `movine_migrations` of the given `kind`, with the SQL they were last run
+/// with as their up migration.
+fn load_tracked(conn: &Connection, kind: &str) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    if !has_kind_column(conn)? {
+        return Ok(migrations);
+    }
+    let mut stmt = conn.prepare(LOAD_TRACKED_MIGRATIONS)?;
+    let rows = stmt.query_map(params![kind], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (name, hash, sql) = row?;
+        let migration = MigrationBuilder::new()
+            .compound_name(&name)
+            .hash(&hash)
+            .lenient()
+            .up_sql(&sql)
+            .build()?;
+        migrations.push(migration);
+    }
+    Ok(migrations)
+}
+
+/// Runs the up SQL of a migration that isn't versioned and records it as `kind`, replacing any
+/// earlier record of it.
+fn run_tracked(conn: &mut Connection, migration: &Migration, step: Step, kind: &str) -> Result<()> {
+    let name = &migration.name;
+    let hash = migration
+        .hash
+        .as_ref()
+        .ok_or_else(|| Error::BadMigration(format!("`{}` has no hash", name)))?;
+    let sql = migration
+        .up_sql
+        .as_ref()
+        .ok_or_else(|| Error::BadMigration(format!("`{}` has no SQL", name)))?;
+
+    with_timeouts(conn, migration, |conn| {
+        let transaction = conn.transaction()?;
+        if !has_kind_column(&transaction)? {
+            transaction.execute_batch(ADD_KIND_COLUMN)?;
+        }
+        execute_statements(migration, step, sql, |statement| {
+            Ok(transaction.execute_batch(statement)?)
+        })?;
+        transaction.execute(FORGET_TRACKED_MIGRATION, params![name, kind])?;
+        transaction.execute(LOG_TRACKED_MIGRATION, params![name, hash, sql, kind])?;
+        transaction.commit()?;
+        Ok(())
+    })
+}
+
+/// The query deleting the row of a versioned migration, which mustn't touch the rows of
+/// repeatable migrations or seeds with the same name.
+fn log_down_sql(conn: &Connection) -> Result<&'static str> {
+    Ok(if has_kind_column(conn)? {
+        LOG_VERSIONED_DOWN_MIGRATION
+    } else {
+        LOG_DOWN_MIGRATION
+    })
+}
+
+/// Whether `movine_migrations` has the `kind` column, which is only added once a repeatable
+/// migration or seed is run. Until then every row is a versioned migration.
+fn has_kind_column(conn: &Connection) -> Result<bool> {
+    let count: i64 = conn.query_row(HAS_KIND_COLUMN, params![], |row| row.get(0))?;
+    Ok(count > 0)
+}
+
+fn load_rows(conn: &Connection, sql: &str) -> Result<Vec<(String, String, String)>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(rows)
+}
+
+/// Runs `f` with the migration's `lock_timeout` as the connection's busy timeout, restoring the
+/// previous busy timeout afterwards. SQLite has no equivalent of `statement_timeout`.
+fn with_timeouts<F>(conn: &mut Connection, migration: &Migration, f: F) -> Result<()>
+where
+    F: FnOnce(&mut Connection) -> Result<()>,
+{
+    if migration.statement_timeout.is_some() {
+        debug!(
+            "Ignoring statement_timeout for {}: not supported by SQLite",
+            migration.name
+        );
+    }
+    let timeout = match migration.lock_timeout {
+        Some(timeout) => timeout,
+        None => return f(conn),
+    };
+
+    let previous: i64 = conn.query_row("PRAGMA busy_timeout", params![], |row| row.get(0))?;
+    conn.busy_timeout(timeout)?;
+    let result = f(conn);
+    conn.busy_timeout(Duration::from_millis(previous as u64))?;
+    result
+}
+
+const LOAD_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+ORDER BY created_at DESC;
+";
+
+const LOAD_VERSIONED_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+WHERE kind = 'versioned'
+ORDER BY created_at DESC;
+";
+
+const REPEATABLE: &str = "repeatable";
+const SEED: &str = "seed";
+
+// The rows of repeatable migrations and seeds keep the SQL they were run with in `down_sql`.
+const LOAD_TRACKED_MIGRATIONS: &str = "\
+SELECT name, hash, down_sql
+FROM movine_migrations
+WHERE kind = $1
+ORDER BY name;
+";
+
+const HAS_KIND_COLUMN: &str = "\
+SELECT COUNT(*)
+FROM pragma_table_info('movine_migrations')
+WHERE name = 'kind';
+";
+
+const ADD_KIND_COLUMN: &str = "\
+ALTER TABLE movine_migrations ADD COLUMN kind TEXT NOT NULL DEFAULT 'versioned';
+";
+
+const LOG_TRACKED_MIGRATION: &str = "\
+INSERT INTO movine_migrations (name, hash, down_sql, kind)
+VALUES ($1, $2, $3, $4);
+";
+
+const FORGET_TRACKED_MIGRATION: &str = "\
+DELETE FROM movine_migrations
+WHERE name = $1 AND kind = $2;
+";
+
 pub const LOG_UP_MIGRATION: &str = "\
 INSERT INTO movine_migrations (name, hash, down_sql)
 VALUES ($1, $2, $3);
@@ -75,6 +324,31 @@ DELETE FROM movine_migrations
 WHERE name = $1;
 ";
 
+const LOG_VERSIONED_DOWN_MIGRATION: &str = "\
+DELETE FROM movine_migrations
+WHERE name = $1 AND kind = 'versioned';
+";
+
+const DUMP_SCHEMA_SQL: &str = "\
+SELECT type, name, sql
+FROM sqlite_master
+WHERE sql IS NOT NULL
+    AND name NOT LIKE 'sqlite_%'
+    AND tbl_name <> 'movine_migrations';
+";
+
+pub const RENAME_MIGRATION: &str = "\
+UPDATE movine_migrations
+SET name = $1, updated_at = CURRENT_TIMESTAMP
+WHERE name = $2;
+";
+
+const RENAME_VERSIONED_MIGRATION: &str = "\
+UPDATE movine_migrations
+SET name = $1, updated_at = CURRENT_TIMESTAMP
+WHERE name = $2 AND kind = 'versioned';
+";
+
 pub const INIT_UP_SQL: &str = "\
 CREATE TABLE movine_migrations (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
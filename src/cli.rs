@@ -1,3 +1,6 @@
+use movine::config::parse_duration;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -9,6 +12,9 @@ pub enum Opt {
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
     },
 
     #[structopt(name = "up")]
@@ -26,9 +32,25 @@ pub enum Opt {
         /// Error out on out-of-order pending migrations.
         strict: bool,
 
+        #[structopt(long = "dump-schema", parse(from_os_str))]
+        /// Write the resulting schema to FILE after migrating.
+        dump_schema: Option<PathBuf>,
+
+        #[structopt(long = "verify-shadow")]
+        /// Check the migrations on a temporary shadow database first, and the resulting schema
+        /// against it afterwards.
+        verify_shadow: bool,
+
+        #[structopt(short = "y", long = "yes")]
+        /// Run the plan without asking for confirmation.
+        yes: bool,
+
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
     },
 
     #[structopt(name = "down")]
@@ -50,9 +72,47 @@ pub enum Opt {
         /// Ignore any unreversable migrations.
         ignore_unreversable: bool,
 
+        #[structopt(short = "y", long = "yes")]
+        /// Run the plan without asking for confirmation.
+        yes: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "migrate")]
+    /// Run or rollback migrations until the database is at the given migration.
+    Migrate {
+        #[structopt(short = "t", long = "to")]
+        /// Name of the migration to end at.
+        to: String,
+
+        #[structopt(short = "p", long = "plan")]
+        /// Do a dry run and show the migration plan.
+        show_plan: bool,
+
+        #[structopt(short = "i", long = "ignore-divergent")]
+        /// Leave divergent migrations after the target applied instead of refusing.
+        ignore_divergent: bool,
+
+        #[structopt(short = "u", long = "ignore-unreversable")]
+        /// Leave unreversable migrations after the target applied instead of refusing.
+        ignore_unreversable: bool,
+
+        #[structopt(short = "y", long = "yes")]
+        /// Run the plan without asking for confirmation.
+        yes: bool,
+
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
     },
 
     #[structopt(name = "fix")]
@@ -62,9 +122,16 @@ pub enum Opt {
         /// Do a dry run and show the migration plan.
         show_plan: bool,
 
+        #[structopt(short = "y", long = "yes")]
+        /// Run the plan without asking for confirmation.
+        yes: bool,
+
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
     },
 
     #[structopt(name = "redo")]
@@ -86,6 +153,164 @@ pub enum Opt {
         /// Ignore any unreversable migrations.
         ignore_unreversable: bool,
 
+        #[structopt(short = "y", long = "yes")]
+        /// Run the plan without asking for confirmation.
+        yes: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "dump-schema")]
+    /// Print the database schema as a sorted, deterministic SQL snapshot.
+    DumpSchema {
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        /// Write the schema to FILE instead of stdout.
+        output: Option<PathBuf>,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "drift")]
+    /// Compare the database schema with a snapshot or with the local migrations.
+    Drift {
+        #[structopt(
+            short = "s",
+            long = "snapshot",
+            default_value = "schema.sql",
+            parse(from_os_str)
+        )]
+        /// Schema snapshot written by `dump-schema` to compare against.
+        snapshot: PathBuf,
+
+        #[structopt(short = "m", long = "from-migrations")]
+        /// Compare against a scratch database built from all local migrations instead.
+        from_migrations: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "squash")]
+    /// Replace the migrations up to NAME with a single baseline migration.
+    Squash {
+        #[structopt(long = "up-to")]
+        /// Last migration to squash.
+        up_to: String,
+
+        #[structopt(long = "from-schema")]
+        /// Build the baseline from the schema the migrations produce on a scratch database
+        /// rather than by concatenating them.
+        from_schema: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "rename")]
+    /// Rename a migration, both locally and in the database.
+    Rename {
+        /// Current name of the migration.
+        old: String,
+
+        /// New name of the migration, including its version prefix.
+        new: String,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "test-reversible")]
+    /// Check on a scratch database that each migration's down undoes its up.
+    TestReversible {
+        /// Migrations to check. All of them if none are given.
+        names: Vec<String>,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "seed")]
+    /// Load the seed data of an environment from `seeds/<env>`.
+    Seed {
+        #[structopt(short = "e", long = "env")]
+        /// Environment whose seeds to run. Defaults to `MOVINE_ENV`, `environment` or `dev`.
+        env: Option<String>,
+
+        #[structopt(long = "once")]
+        /// Skip seeds that have been run before, even if they were edited since.
+        once: bool,
+
+        #[structopt(short = "p", long = "plan")]
+        /// Do a dry run and show the seeds that would run.
+        show_plan: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "check")]
+    /// Check the migrations against the `[policy]` in `movine.toml`.
+    Check {
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "lint")]
+    /// Check the pending migrations for operations that are risky on a live database.
+    Lint {
+        #[structopt(short = "v", long = "verbose")]
+        /// Run with verbose logging
+        debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+    },
+
+    #[structopt(name = "validate")]
+    /// Check that every migration runs, on an in-memory SQLite database.
+    Validate {
+        #[structopt(long = "shadow")]
+        /// Run the migrations on a temporary shadow database on the configured server instead,
+        /// and compare its schema with the database's.
+        shadow: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
+
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
@@ -93,6 +318,7 @@ pub enum Opt {
 
     #[structopt(name = "custom")]
     /// [unimplemented]
+    #[allow(dead_code)]
     Custom {
         #[structopt(short = "p", long = "plan")]
         /// Do a dry run and show the migration plan.
@@ -108,6 +334,18 @@ pub enum Opt {
     #[structopt(name = "generate")]
     /// Generate a migration with a given name.
     Generate {
+        #[structopt(short = "t", long = "template")]
+        /// Start from the named template in the templates directory.
+        template: Option<String>,
+
+        #[structopt(long = "single-file")]
+        /// Write a single NAME.sql file with `-- +movine Up`/`-- +movine Down` sections.
+        single_file: bool,
+
+        #[structopt(short = "d", long = "description")]
+        /// Describe the migration in a `-- +movine description` directive.
+        description: Option<String>,
+
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
@@ -121,5 +359,16 @@ pub enum Opt {
         #[structopt(short = "v", long = "verbose")]
         /// Run with verbose logging
         debug: bool,
+
+        #[structopt(flatten)]
+        connection: ConnectionOpts,
     },
 }
+
+/// Options for the subcommands that connect to the database.
+#[derive(Debug, StructOpt)]
+pub struct ConnectionOpts {
+    #[structopt(short = "w", long = "wait", parse(try_from_str = parse_duration))]
+    /// Wait up to TIMEOUT (e.g. `30s`) for the database to accept connections.
+    pub wait: Option<Duration>,
+}
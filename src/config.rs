@@ -1,18 +1,28 @@
 use crate::errors::{Error, Result};
+use crate::file_handler::Layout;
+use crate::hooks::HookCommands;
+use crate::lint::LintConfig;
+use crate::migration::NamingScheme;
+use crate::policy::Policy;
 use crate::DbAdaptor;
-use log::debug;
+use log::{debug, info, warn};
 #[cfg(feature = "with-native-tls")]
 use native_tls::{Certificate, TlsConnector};
+use postgres::error::SqlState;
 #[cfg(feature = "with-native-tls")]
 use postgres_native_tls::MakeTlsConnector;
 #[cfg(feature = "with-rustls")]
 use rustls::ClientConfig;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::error::Error as StdError;
 #[cfg(feature = "with-native-tls")]
 use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
 #[cfg(feature = "with-rustls")]
 use tokio_postgres_rustls::MakeRustlsConnect;
 
@@ -24,11 +34,38 @@ use self::postgres_params::RawPostgresParams;
 use sqlite_params::RawSqliteParams;
 pub use sqlite_params::SqliteParams;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Config {
     pub postgres: Option<PostgresParams>,
     pub sqlite: Option<SqliteParams>,
     pub database_url: Option<String>,
+    pub connect_timeout: Option<Duration>,
+    pub connect_retries: Option<u32>,
+    pub lock_timeout: Option<Duration>,
+    pub statement_timeout: Option<Duration>,
+    pub migration_retries: Option<u32>,
+    pub migration_retry_backoff: Option<Duration>,
+    pub templates_dir: Option<String>,
+    pub default_template: Option<String>,
+    pub naming: NamingScheme,
+    pub layout: Layout,
+    pub lint: LintConfig,
+    pub policy: Policy,
+    pub hooks_dir: Option<String>,
+    pub hooks: HookCommands,
+    pub seeds_dir: Option<String>,
+    /// The environment the database belongs to, from `MOVINE_ENV` or `environment`.
+    pub environment: Option<String>,
+    pub environments: HashMap<String, Environment>,
+}
+
+/// Settings for one environment, from its `[environments.<name>]` section.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    /// Refuse to run seeds against this environment, and ask before running migration plans
+    /// against it.
+    pub protected: bool,
 }
 
 impl Config {
@@ -47,14 +84,6 @@ impl Config {
             &raw_config, &pg_env_params, &sqlite_env_params, &database_url
         );
 
-        if let Ok(database_url) = database_url {
-            debug!("Using database_url provided.");
-            return Ok(Config {
-                database_url: Some(database_url),
-                ..Self::default()
-            });
-        }
-
         let raw_config = match raw_config {
             Ok(raw_config) => Some(raw_config),
             Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -66,14 +95,90 @@ impl Config {
             }
         };
 
+        let config = if let Ok(database_url) = database_url {
+            debug!("Using database_url provided.");
+            Config {
+                database_url: Some(database_url),
+                ..Self::default()
+            }
+        } else {
+            Self::load_params(raw_config.as_ref(), pg_env_params, sqlite_env_params)?
+        };
+        let mut config = match raw_config {
+            Some(raw_config) => config.with_settings(raw_config)?,
+            None => config,
+        };
+        if let Ok(environment) = std::env::var("MOVINE_ENV") {
+            config.environment = Some(environment);
+        }
+
+        Ok(config)
+    }
+
+    /// Loads only the settings from the config file, without any connection parameters, for
+    /// commands that don't need a database. Without a config file the defaults are used.
+    pub fn load_settings(file: &str) -> Result<Self> {
+        match RawConfig::load_file(file) {
+            Ok(raw_config) => Self::default().with_settings(raw_config),
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("Config file not found.");
+                Ok(Self::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Applies the settings from the config file other than the connection parameters.
+    fn with_settings(mut self, raw_config: RawConfig) -> Result<Self> {
+        self.connect_timeout = raw_config
+            .connect_timeout
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?;
+        self.connect_retries = raw_config.connect_retries;
+        self.lock_timeout = raw_config
+            .lock_timeout
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?;
+        self.statement_timeout = raw_config
+            .statement_timeout
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?;
+        self.migration_retries = raw_config.migration_retries;
+        self.migration_retry_backoff = raw_config
+            .migration_retry_backoff
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?;
+        self.templates_dir = raw_config.templates_dir;
+        self.default_template = raw_config.default_template;
+        self.naming = raw_config.naming.unwrap_or_default();
+        self.layout = raw_config.layout.unwrap_or_default();
+        self.lint = raw_config.lint.unwrap_or_default();
+        self.policy = raw_config.policy.unwrap_or_default();
+        self.hooks_dir = raw_config.hooks_dir;
+        self.hooks = raw_config.hooks.unwrap_or_default();
+        self.seeds_dir = raw_config.seeds_dir;
+        self.environment = raw_config.environment;
+        self.environments = raw_config.environments.unwrap_or_default();
+        Ok(self)
+    }
+
+    fn load_params(
+        raw_config: Option<&RawConfig>,
+        pg_env_params: Result<RawPostgresParams>,
+        sqlite_env_params: Result<RawSqliteParams>,
+    ) -> Result<Self> {
         match raw_config {
             Some(RawConfig {
                 postgres: Some(pg_params),
                 ..
             }) => {
                 debug!("Using postgres config-file params provided.");
-                let all_params = [Ok(pg_params), pg_env_params];
-                let params: Vec<_> = all_params.iter().filter_map(|x| x.as_ref().ok()).collect();
+                let all_params = [Some(pg_params), pg_env_params.as_ref().ok()];
+                let params: Vec<_> = all_params.iter().filter_map(|x| *x).collect();
                 let params: PostgresParams = (&params[..]).try_into()?;
                 Ok(Self {
                     postgres: Some(params),
@@ -85,8 +190,8 @@ impl Config {
                 ..
             }) => {
                 debug!("Using sqlite config-file params provided.");
-                let all_params = [Ok(sqlite_params), sqlite_env_params];
-                let params: Vec<_> = all_params.iter().filter_map(|x| x.as_ref().ok()).collect();
+                let all_params = [Some(sqlite_params), sqlite_env_params.as_ref().ok()];
+                let params: Vec<_> = all_params.iter().filter_map(|x| *x).collect();
                 let params = (&params[..]).try_into()?;
                 Ok(Self {
                     sqlite: Some(params),
@@ -120,7 +225,8 @@ impl Config {
     pub fn into_pg_conn_from_url(self) -> Result<postgres::Client> {
         if let Some(ref url) = self.database_url {
             if url.starts_with("postgres") {
-                let conn = postgres::Client::connect(url, postgres::NoTls)?;
+                let conn = self
+                    .connect_with_retry(|| Ok(postgres::Client::connect(url, postgres::NoTls)?))?;
                 Ok(conn)
             } else {
                 Err(Error::AdaptorNotFound)
@@ -149,11 +255,13 @@ impl Config {
                     database = params.database,
                 ),
             };
-            let conn = if let Some(cert) = &params.sslrootcert {
-                build_tls_connection(&url, cert)?
-            } else {
-                postgres::Client::connect(&url, postgres::NoTls)?
-            };
+            let conn = self.connect_with_retry(|| {
+                if let Some(cert) = &params.sslrootcert {
+                    build_tls_connection(&url, cert)
+                } else {
+                    Ok(postgres::Client::connect(&url, postgres::NoTls)?)
+                }
+            })?;
 
             Ok(conn)
         } else {
@@ -161,9 +269,22 @@ impl Config {
         }
     }
 
+    /// A copy of this config that points at `database` on the same Postgres server.
+    pub fn with_database(&self, database: &str) -> Self {
+        let mut config = self.clone();
+        if let Some(params) = &mut config.postgres {
+            params.database = database.to_owned();
+        }
+        if let Some(url) = &mut config.database_url {
+            *url = replace_url_database(url, database);
+        }
+        config
+    }
+
     pub fn into_sqlite_conn(self) -> Result<rusqlite::Connection> {
         if let Some(ref params) = self.sqlite {
-            let conn = rusqlite::Connection::open(&params.file)?;
+            let conn = rusqlite::Connection::open(&params.file)
+                .map_err(|e| Error::ConnectionError(Box::new(e.into())))?;
             Ok(conn)
         } else {
             Err(Error::AdaptorNotFound)
@@ -185,12 +306,128 @@ impl Config {
             _ => Err(Error::AdaptorNotFound),
         }
     }
+
+    /// Runs `connect` until it succeeds, backing off exponentially between attempts for as long
+    /// as `connect_timeout` and `connect_retries` allow. Only errors that look like the server
+    /// not accepting connections yet are retried; anything else fails immediately.
+    fn connect_with_retry<T, F>(&self, mut connect: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let start = Instant::now();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            debug!("Connecting to the database (attempt {})", attempt);
+            let error = match connect() {
+                Ok(conn) => return Ok(conn),
+                Err(e) => e,
+            };
+
+            if !is_transient_connection_error(&error) {
+                return Err(Error::ConnectionError(Box::new(error)));
+            }
+            let out_of_attempts = match (self.connect_retries, self.connect_timeout) {
+                (None, None) => true,
+                (Some(retries), _) if attempt > retries => true,
+                (_, Some(timeout)) if start.elapsed() + backoff > timeout => true,
+                _ => false,
+            };
+            if out_of_attempts {
+                if attempt > 1 {
+                    warn!("Giving up on the database after {} attempts", attempt);
+                }
+                return Err(Error::ConnectionError(Box::new(error)));
+            }
+
+            info!(
+                "Unable to connect to the database (attempt {}): {}. Retrying in {:?}",
+                attempt, error, backoff
+            );
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            attempt += 1;
+        }
+    }
+}
+
+/// Replaces the path of a `postgres://user@host:port/database?options` URL.
+fn replace_url_database(url: &str, database: &str) -> String {
+    let (base, options) = url.split_at(url.find('?').unwrap_or(url.len()));
+    let authority_start = base.find("://").map_or(0, |i| i + 3);
+    let path_start = base[authority_start..]
+        .find('/')
+        .map_or(base.len(), |i| authority_start + i);
+    format!("{}/{}{}", &base[..path_start], database, options)
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Whether a connection error is worth retrying, i.e. the server is not (yet) accepting
+/// connections. Authentication and configuration errors are permanent.
+fn is_transient_connection_error(error: &Error) -> bool {
+    match error {
+        Error::PgError(e) => {
+            if let Some(code) = e.code() {
+                return *code == SqlState::CANNOT_CONNECT_NOW;
+            }
+            if e.is_closed() {
+                return true;
+            }
+            let mut source = e.source();
+            while let Some(cause) = source {
+                if cause.is::<std::io::Error>() {
+                    return true;
+                }
+                source = cause.source();
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5min` or `1h`. A bare number is read as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let trimmed = value.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| Error::InvalidDuration(value.to_owned()))?;
+    match unit.trim() {
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "ms" => Ok(Duration::from_millis(number)),
+        "m" | "min" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(Error::InvalidDuration(value.to_owned())),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RawConfig {
     pub postgres: Option<RawPostgresParams>,
     pub sqlite: Option<RawSqliteParams>,
+    pub connect_timeout: Option<String>,
+    pub connect_retries: Option<u32>,
+    pub lock_timeout: Option<String>,
+    pub statement_timeout: Option<String>,
+    pub migration_retries: Option<u32>,
+    pub migration_retry_backoff: Option<String>,
+    pub templates_dir: Option<String>,
+    pub default_template: Option<String>,
+    pub naming: Option<NamingScheme>,
+    pub layout: Option<Layout>,
+    pub lint: Option<LintConfig>,
+    pub policy: Option<Policy>,
+    pub hooks_dir: Option<String>,
+    pub hooks: Option<HookCommands>,
+    pub seeds_dir: Option<String>,
+    pub environment: Option<String>,
+    pub environments: Option<HashMap<String, Environment>>,
 }
 
 impl RawConfig {
@@ -227,3 +464,133 @@ fn build_tls_connection(url: &str, certificate: &str) -> Result<postgres::Client
     let tls = MakeRustlsConnect::new(config);
     Ok(postgres::Client::connect(&url, tls)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Durations should accept the common units and treat bare numbers as seconds.
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("5min").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+    }
+
+    /// A connection error the way it comes back while the server isn't listening yet.
+    fn refused_connection() -> Error {
+        match postgres::Client::connect("host=127.0.0.1 port=1 user=movine", postgres::NoTls) {
+            Ok(_) => panic!("nothing should be listening on port 1"),
+            Err(e) => e.into(),
+        }
+    }
+
+    #[test]
+    /// Only errors from a server that isn't accepting connections yet should be retried.
+    fn test_is_transient_connection_error() {
+        assert!(is_transient_connection_error(&refused_connection()));
+        assert!(!is_transient_connection_error(&Error::AdaptorNotFound));
+    }
+
+    #[test]
+    /// Connecting should be retried `connect_retries` times on transient errors only, and stop
+    /// as soon as it succeeds.
+    fn test_connect_with_retry() {
+        let config = Config {
+            connect_retries: Some(2),
+            ..Config::default()
+        };
+
+        let mut attempts = 0;
+        let result: Result<()> = config.connect_with_retry(|| {
+            attempts += 1;
+            Err(refused_connection())
+        });
+        assert!(matches!(result, Err(Error::ConnectionError(_))));
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<()> = config.connect_with_retry(|| {
+            attempts += 1;
+            Err(Error::AdaptorNotFound)
+        });
+        assert!(matches!(result, Err(Error::ConnectionError(_))));
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result = config.connect_with_retry(|| {
+            attempts += 1;
+            if attempts < 2 {
+                Err(refused_connection())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let mut attempts = 0;
+        let result: Result<()> = Config::default().connect_with_retry(|| {
+            attempts += 1;
+            Err(refused_connection())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    /// Pointing a database URL at another database should keep everything else.
+    fn test_replace_url_database() {
+        assert_eq!(
+            replace_url_database("postgres://u:p@host:5432/app?sslmode=require", "scratch"),
+            "postgres://u:p@host:5432/scratch?sslmode=require"
+        );
+        assert_eq!(
+            replace_url_database("postgresql://host", "scratch"),
+            "postgresql://host/scratch"
+        );
+    }
+
+    #[test]
+    /// With `DATABASE_URL` set, the settings in the config file should still apply, and a file
+    /// that can't be parsed should be an error rather than dropping them.
+    fn test_load_with_database_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movine.toml");
+        let file = file.to_str().unwrap();
+        std::env::set_var("DATABASE_URL", "postgres://movine@localhost/movine");
+
+        std::fs::write(
+            file,
+            "lock_timeout = \"5s\"\n\n[policy]\nrequire_down = true\n",
+        )
+        .unwrap();
+        let config = Config::load(file);
+        std::fs::write(file, "lock_timeout = \"5 sec\"\n").unwrap();
+        let invalid = Config::load(file);
+        std::env::remove_var("DATABASE_URL");
+
+        let config = config.unwrap();
+        assert_eq!(config.lock_timeout, Some(Duration::from_secs(5)));
+        assert!(config.policy.require_down);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    /// Loading only the settings should need neither connection parameters nor a config file.
+    fn test_load_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movine.toml");
+        let file = file.to_str().unwrap();
+        let config = Config::load_settings(file).unwrap();
+        assert!(config.postgres.is_none() && config.sqlite.is_none());
+
+        std::fs::write(file, "naming = \"sequential\"\n\n[postgres]\n").unwrap();
+        let config = Config::load_settings(file).unwrap();
+        assert_eq!(config.naming, NamingScheme::Sequential);
+        assert!(config.postgres.is_none());
+    }
+}
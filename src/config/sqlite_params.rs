@@ -2,7 +2,7 @@ use crate::errors::{Error, Result};
 use serde::Deserialize; //::{params, Connection, Result};
 use std::convert::TryFrom;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SqliteParams {
     pub file: String,
 }
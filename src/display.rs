@@ -1,8 +1,14 @@
+use crate::errors::Error;
+use crate::lint::Warning;
 use crate::match_maker::Matching;
 use crate::migration::Migration;
 use crate::plan_builder::Step;
+use crate::schema::Difference;
+use crate::RoundTrip;
 use ansi_term::Color;
+use std::fmt::Display;
 use std::io::{self, Write};
+use std::time::Duration;
 
 const LIGHT_RED: u8 = 9;
 
@@ -37,6 +43,17 @@ pub fn print_status(matchings: &[Matching]) {
     }
 }
 
+pub fn print_repeatable_status(repeatables: &[(&str, bool)]) {
+    for (name, current) in repeatables {
+        let status = if *current {
+            Color::Green.paint("  Current")
+        } else {
+            Color::Yellow.paint(" Outdated")
+        };
+        println!("{} - {}", status, name);
+    }
+}
+
 pub fn print_plan(plan: &[(Step, &Migration)]) {
     for step in plan.iter() {
         print_step(step);
@@ -45,11 +62,13 @@ pub fn print_plan(plan: &[(Step, &Migration)]) {
 
 pub fn print_step((step, migration): &(Step, &Migration)) {
     use Step::*;
-    if migration.is_reversable() || step == &Step::Up {
+    if migration.is_reversable() || step != &Step::Down {
         let step = match step {
-            // Add spaces in front to make them all the same length
+            // Add spaces in front to make them the same length as `Down`
             Up => "  Up",
             Down => "Down",
+            Repeat => "Repeat",
+            Seed => "Seed",
         };
 
         println!(
@@ -65,3 +84,188 @@ pub fn print_step((step, migration): &(Step, &Migration)) {
         );
     }
 }
+
+/// Asks whether to run the plan just shown, and reads a yes or no from stdin.
+pub fn ask_confirmation() -> io::Result<bool> {
+    print!("Run this plan? [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub fn print_retry(migration: &Migration, attempt: u32, delay: Duration, error: &dyn Display) {
+    println!(
+        "{retry} - {name} failed (attempt {attempt}), retrying in {delay}ms: {error}",
+        retry = Color::Yellow.paint("Retry"),
+        name = migration.name,
+        attempt = attempt,
+        delay = delay.as_millis(),
+        error = error,
+    );
+}
+
+pub fn print_round_trip(name: &str, round_trip: &RoundTrip) {
+    match round_trip {
+        // Add spaces in front to make them all the same length
+        RoundTrip::Reversible => println!("{} - {}", Color::Green.paint("  Reversible"), name),
+        RoundTrip::NoDown => println!(
+            "{} - {} has no down migration",
+            Color::Yellow.paint("     Skipped"),
+            name
+        ),
+        RoundTrip::DownFailed(error) => {
+            println!("{} - {}", Color::Red.paint("Irreversible"), name);
+            println!("{}", indent(&error.to_string()));
+        }
+        RoundTrip::NotRestored(differences) => {
+            println!(
+                "{} - {} (down doesn't restore the schema from before up)",
+                Color::Red.paint("Irreversible"),
+                name
+            );
+            print_drift(differences);
+        }
+        RoundTrip::NotReapplied(differences) => {
+            println!(
+                "{} - {} (up gives a different schema the second time)",
+                Color::Red.paint("Irreversible"),
+                name
+            );
+            print_drift(differences);
+        }
+    }
+}
+
+pub fn print_round_trip_aborted(name: &str, error: &Error) {
+    println!(
+        "{} - could not apply {} again, so the remaining migrations were not checked:",
+        Color::Red.paint("     Stopped"),
+        name
+    );
+    println!("{}", indent(&error.to_string()));
+}
+
+pub fn print_validated(count: usize) {
+    println!(
+        "{} - all {} migration(s) ran successfully",
+        Color::Green.paint("Valid"),
+        count
+    );
+}
+
+pub fn print_shadow(count: usize) {
+    println!(
+        "{} - running {} migration(s) on the shadow database first",
+        Color::Green.paint("Shadow"),
+        count
+    );
+}
+
+pub fn print_lint_warning(migration: &str, warning: &Warning) {
+    let rule = Color::Yellow.paint(warning.rule.as_str());
+    match &warning.statement {
+        Some((line, statement)) => {
+            println!(
+                "{} - {} line {}: {}",
+                rule,
+                migration,
+                line,
+                warning.rule.message()
+            );
+            println!("{}", indent(statement));
+        }
+        None => println!("{} - {}: {}", rule, migration, warning.rule.message()),
+    }
+}
+
+pub fn print_lint_clean(count: usize) {
+    println!(
+        "{} - no risky operations in {} pending migration(s)",
+        Color::Green.paint("Lint"),
+        count
+    );
+}
+
+pub fn print_policy_ok(count: usize) {
+    println!(
+        "{} - all {} migration(s) follow the policy",
+        Color::Green.paint("Policy"),
+        count
+    );
+}
+
+pub fn print_hook(hook: &str) {
+    println!("{} - {}", Color::Cyan.paint("Hook"), hook);
+}
+
+pub fn print_drift(differences: &[Difference]) {
+    if differences.is_empty() {
+        println!(
+            "{} - the database matches the expected schema",
+            Color::Green.paint("No drift")
+        );
+    }
+    for difference in differences {
+        match difference {
+            // Add spaces in front to make them all the same length
+            Difference::Added { object, definition } => {
+                println!("{} - {}", Color::Red.paint("  Added"), object);
+                println!("{}", indent(definition));
+            }
+            Difference::Removed { object, definition } => {
+                println!("{} - {}", Color::Red.paint("Removed"), object);
+                println!("{}", indent(definition));
+            }
+            Difference::Changed {
+                object,
+                expected,
+                actual,
+            } => {
+                println!("{} - {}", Color::Fixed(LIGHT_RED).paint("Changed"), object);
+                println!("    expected:\n{}", indent(&indent(expected)));
+                println!("    actual:\n{}", indent(&indent(actual)));
+            }
+        }
+    }
+}
+
+pub fn print_squash(baseline: &str, count: usize) {
+    println!(
+        "{} - {} migration(s) into {}",
+        Color::Green.paint("Squashed"),
+        count,
+        baseline
+    );
+}
+
+pub fn print_squash_database(baseline: &str, count: usize) {
+    println!(
+        "{} - replaced {} row(s) in movine_migrations with {}",
+        Color::Green.paint("Squashed"),
+        count,
+        baseline
+    );
+}
+
+pub fn print_renames(renames: &[(&str, &str)]) {
+    for (old, new) in renames {
+        println!(
+            "\n{old} looks like it was renamed to {new}. \
+             Run `movine rename {old} {new}` to update the database.",
+            old = old,
+            new = new,
+        );
+    }
+}
+
+pub fn print_rename(old: &str, new: &str) {
+    println!("{} - {} to {}", Color::Green.paint("Renamed"), old, new);
+}
+
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
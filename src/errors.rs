@@ -1,10 +1,15 @@
+use crate::migration::Migration;
+use crate::plan_builder::Step;
+use crate::policy::Violation;
 use libsqlite3_sys::Error as SqliteLibError;
 use libsqlite3_sys::ErrorCode as SqliteLibErrorCode;
 use postgres::error::Error as PostgresError;
+use postgres::error::{ErrorPosition, SqlState};
 use rusqlite::Error as SqliteError;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use toml::de::Error as TomlError;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -21,13 +26,56 @@ pub enum Error {
     SqliteParamError {
         file: bool,
     },
-    BadMigration,
+    BadMigration(String),
+    InvalidDuration(String),
     Unknown,
     AdaptorNotFound,
     MigrationDirNotFound,
+    TemplateNotFound(String),
+    MixedNamingSchemes(Vec<String>),
+    DuplicateMigration(String),
+    LoadMigration {
+        path: PathBuf,
+        reason: String,
+    },
     DirtyMigrations,
     DivergentMigration,
     UnrollbackableMigration,
+    MigrationTimeout {
+        migration: String,
+        error: Box<Error>,
+    },
+    MigrationFailed {
+        migration: String,
+        step: Step,
+        /// The failing statement and its 1-based line and column in the step's SQL, when the
+        /// database reports where the error occurred.
+        location: Option<SqlLocation>,
+        error: Box<Error>,
+    },
+    ConnectionError(Box<Error>),
+    InvalidSchema(String),
+    MigrationNotFound(String),
+    MigrationExists(String),
+    InvalidMigrationName {
+        name: String,
+        reason: &'static str,
+    },
+    SquashPartiallyApplied(String),
+    SchemaDrift(usize),
+    NotReversible(Vec<String>),
+    UnknownLintRule(String),
+    LintFailed(usize),
+    PolicyViolations(Vec<Violation>),
+    HookFailed {
+        hook: String,
+        reason: String,
+    },
+    /// Seeding was refused because the environment is protected.
+    ProtectedEnvironment(String),
+    PlanNotConfirmed,
+    /// The database adaptor doesn't implement what the command needs.
+    Unsupported(&'static str),
     IoError(io::Error),
     TomlError(TomlError),
     PgError(PostgresError),
@@ -41,18 +89,44 @@ pub enum Error {
     RustlsPemfileError,
 }
 
-impl fmt::Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Error::*;
         match self {
             ConfigNotFound => write!(f, "`movine.toml` config file not found and no environment variables were found."),
-            BadMigration => write!(f, "Error parsing migrations."),
+            BadMigration(reason) => write!(f, "Error parsing migrations: {}", reason),
+            InvalidDuration(value) => write!(f, "Invalid duration `{}`. Use a number followed by `ms`, `s`, `min` or `h`.", value),
             Unknown => write!(f, "Unknown error occurred"),
             AdaptorNotFound => write!(f, "Could not find adaptor"),
             MigrationDirNotFound => write!(f, "Could not find migration directory"),
+            MixedNamingSchemes(found) => write!(f, "Migrations must all use the same naming scheme, but found:\n{}", found.join("\n")),
+            LoadMigration { path, reason } => write!(f, "Unable to load migration `{}`: {}", path.display(), reason),
+            DuplicateMigration(name) => write!(f, "Migration `{}` exists in more than one layout", name),
+            TemplateNotFound(name) => write!(f, "Could not find migration template `{}`", name),
             DirtyMigrations => write!(f, "More recent migrations exist in the database than the pending migrations. This is an error when run with --strict"),
             DivergentMigration => write!(f, "Divergent migration found. Run with --ignore-divergent to ignore divergent migrations."),
             UnrollbackableMigration => write!(f, "Can't rollback one of the migrations in the list. Consider changing your parameters or adding a `down.sql` migration."),
+            MigrationTimeout { migration, error } => write!(f, "Migration `{}` timed out. Consider raising its `lock_timeout` or `statement_timeout`.\n{}", migration, error),
+            MigrationFailed { migration, step, location: Some(location), error } => write!(f, "Migration `{}` failed running {:?} at line {}, column {}:\n    {}\n{}", migration, step, location.line, location.column, location.statement.replace('\n', "\n    "), error),
+            MigrationFailed { migration, step, location: None, error } => write!(f, "Migration `{}` failed running {:?}:\n{}", migration, step, error),
+            MigrationNotFound(name) => write!(f, "Could not find migration `{}`", name),
+            MigrationExists(name) => write!(f, "Migration `{}` already exists", name),
+            InvalidMigrationName { name, reason } => write!(f, "Invalid migration name `{}`: {}", name, reason),
+            SquashPartiallyApplied(name) => write!(f, "The database has only some of the migrations up to `{}` applied. Bring it up to date before squashing.", name),
+            InvalidSchema(reason) => write!(f, "Unable to read schema snapshot: {}", reason),
+            SchemaDrift(count) => write!(f, "Found {} difference(s) between the database and the expected schema", count),
+            NotReversible(names) => write!(f, "The down migration of these migrations doesn't undo their up migration:\n{}", names.join("\n")),
+            UnknownLintRule(name) => write!(f, "Unknown lint rule `{}`", name),
+            LintFailed(count) => write!(f, "Found {} risky operation(s) in the pending migrations", count),
+            PolicyViolations(violations) => {
+                write!(f, "Migrations break the policy in `movine.toml`:")?;
+                violations.iter().try_for_each(|violation| write!(f, "\n  {}", violation))
+            }
+            HookFailed { hook, reason } => write!(f, "Hook `{}` failed: {}", hook, reason),
+            PlanNotConfirmed => write!(f, "The migration plan was not confirmed. Run with --yes to run it without asking."),
+            Unsupported(feature) => write!(f, "The database adaptor does not support {}", feature),
+            ProtectedEnvironment(name) => write!(f, "Environment `{}` is protected in `movine.toml`; refusing to seed it", name),
+            ConnectionError(e) => write!(f, "Unable to connect to the database: {}", e),
             IoError(e) => write!(f, "IO Error: {}", e),
             TomlError(e) => write!(f, "Unable to read config file: {}", e),
             PgError(e) => write!(f, "Error in Postgres: {}", e),
@@ -83,9 +157,167 @@ impl fmt::Debug for Error {
     }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+/// Where in a migration's SQL an error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlLocation {
+    pub statement: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Process exit codes used by the `movine` binary, one per class of error:
+///
+/// | Code | Meaning |
+/// |------|---------|
+/// | 0    | Success |
+/// | 1    | Any other error, e.g. a failed query outside a migration or a command-line usage error |
+/// | 2    | Configuration error, including rejected database credentials |
+/// | 3    | Could not connect to the database |
+/// | 4    | The migration plan was refused (dirty, divergent or irreversible migrations, a protected environment, or no confirmation) |
+/// | 5    | A migration (or one of its hooks) failed to run |
+/// | 6    | The local migrations could not be loaded |
+/// | 7    | The database schema has drifted from the expected schema |
+/// | 8    | A migration's down migration doesn't undo its up migration |
+/// | 9    | `lint` found risky operations in the pending migrations |
+/// | 10   | Migrations break the `[policy]` in `movine.toml` |
+pub mod exit_code {
+    pub const OTHER: i32 = 1;
+    pub const CONFIG: i32 = 2;
+    pub const CONNECTION: i32 = 3;
+    pub const PLAN_REFUSED: i32 = 4;
+    pub const MIGRATION_FAILED: i32 = 5;
+    pub const BAD_MIGRATIONS: i32 = 6;
+    pub const SCHEMA_DRIFT: i32 = 7;
+    pub const NOT_REVERSIBLE: i32 = 8;
+    pub const LINT: i32 = 9;
+    pub const POLICY: i32 = 10;
+}
+
+impl Error {
+    /// The process exit code for this error; see [`exit_code`].
+    pub fn exit_code(&self) -> i32 {
+        use Error::*;
+        match self {
+            ConfigNotFound
+            | PgParamError { .. }
+            | SqliteParamError { .. }
+            | InvalidDuration(_)
+            | AdaptorNotFound
+            | TemplateNotFound(_)
+            | UnknownLintRule(_)
+            | TomlError(_)
+            | Envy(_) => exit_code::CONFIG,
+            #[cfg(feature = "with-native-tls")]
+            NativeTlsError(_) => exit_code::CONFIG,
+            #[cfg(feature = "with-rustls")]
+            RustlsError(_) | RustlsPemfileError => exit_code::CONFIG,
+            ConnectionError(e) if e.is_auth_failure() => exit_code::CONFIG,
+            ConnectionError(_) => exit_code::CONNECTION,
+            DirtyMigrations
+            | DivergentMigration
+            | UnrollbackableMigration
+            | SquashPartiallyApplied(_)
+            | ProtectedEnvironment(_)
+            | PlanNotConfirmed => exit_code::PLAN_REFUSED,
+            MigrationTimeout { .. } | MigrationFailed { .. } | HookFailed { .. } => {
+                exit_code::MIGRATION_FAILED
+            }
+            BadMigration(_)
+            | MigrationDirNotFound
+            | MixedNamingSchemes(_)
+            | DuplicateMigration(_)
+            | LoadMigration { .. } => exit_code::BAD_MIGRATIONS,
+            SchemaDrift(_) => exit_code::SCHEMA_DRIFT,
+            NotReversible(_) => exit_code::NOT_REVERSIBLE,
+            LintFailed(_) => exit_code::LINT,
+            PolicyViolations(_) => exit_code::POLICY,
+            Unknown
+            | Unsupported(_)
+            | InvalidSchema(_)
+            | MigrationNotFound(_)
+            | MigrationExists(_)
+            | InvalidMigrationName { .. }
+            | IoError(_)
+            | PgError(_)
+            | SqliteError(_) => exit_code::OTHER,
+        }
+    }
+
+    /// Whether the database rejected the credentials, which is a configuration problem rather
+    /// than an unreachable database.
+    fn is_auth_failure(&self) -> bool {
+        match self {
+            Error::PgError(e) => e.code().is_some_and(is_auth_failure_code),
+            _ => false,
+        }
+    }
+
+    /// The 1-based character offset into the executed SQL at which the database reported the
+    /// error, if it did.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            Error::PgError(e) => match e.as_db_error()?.position()? {
+                ErrorPosition::Original(position) => Some(*position as usize),
+                ErrorPosition::Internal { .. } => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether this error was caused by the `lock_timeout`/`statement_timeout` (or, for SQLite,
+    /// `busy_timeout`) of `migration` expiring. A cancelled query or a lock that isn't available
+    /// only counts if the matching timeout was set, so that e.g. `pg_cancel_backend` is reported
+    /// as a failure.
+    pub fn is_timeout(&self, migration: &Migration) -> bool {
+        match self {
+            Error::PgError(e) => match e.code() {
+                Some(code) if *code == SqlState::LOCK_NOT_AVAILABLE => {
+                    migration.lock_timeout.is_some()
+                }
+                Some(code) if *code == SqlState::QUERY_CANCELED => {
+                    migration.statement_timeout.is_some()
+                }
+                _ => false,
+            },
+            Error::SqliteError(rusqlite::Error::SqliteFailure(
+                SqliteLibError {
+                    code: SqliteLibErrorCode::DatabaseBusy,
+                    ..
+                },
+                _,
+            )) => migration.lock_timeout.is_some(),
+            Error::MigrationTimeout { .. } => true,
+            Error::MigrationFailed { error, .. } => error.is_timeout(migration),
+            _ => false,
+        }
+    }
+
+    /// Whether the failed migration may succeed if simply run again: it lost a lock or
+    /// serialization conflict rather than containing an error.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::MigrationFailed { error, .. } => error.is_retryable(),
+            Error::PgError(e) => matches!(
+                e.code(),
+                Some(code) if *code == SqlState::LOCK_NOT_AVAILABLE
+                    || *code == SqlState::T_R_DEADLOCK_DETECTED
+                    || *code == SqlState::T_R_SERIALIZATION_FAILURE
+            ),
+            Error::SqliteError(rusqlite::Error::SqliteFailure(
+                SqliteLibError {
+                    code: SqliteLibErrorCode::DatabaseBusy,
+                    ..
+                },
+                _,
+            )) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
     }
 }
 
@@ -135,3 +367,69 @@ impl From<rustls::TLSError> for Error {
 
 // Implements std::Error for ease of use outside of Movine
 impl StdError for Error {}
+
+fn is_auth_failure_code(code: &SqlState) -> bool {
+    *code == SqlState::INVALID_PASSWORD || *code == SqlState::INVALID_AUTHORIZATION_SPECIFICATION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::MigrationBuilder;
+
+    #[test]
+    /// A busy database should only count as a timeout if the migration set a lock timeout.
+    fn test_is_timeout() {
+        let busy = Error::SqliteError(rusqlite::Error::SqliteFailure(
+            SqliteLibError::new(libsqlite3_sys::SQLITE_BUSY),
+            None,
+        ));
+        let with_timeout = MigrationBuilder::new()
+            .compound_name("with_timeout")
+            .up_sql("-- +movine lock_timeout 1s\nSELECT 1;")
+            .build()
+            .unwrap();
+        let without_timeout = MigrationBuilder::new()
+            .compound_name("without_timeout")
+            .up_sql("SELECT 1;")
+            .build()
+            .unwrap();
+        assert!(busy.is_timeout(&with_timeout));
+        assert!(!busy.is_timeout(&without_timeout));
+        assert!(!Error::Unknown.is_timeout(&with_timeout));
+    }
+
+    #[test]
+    /// Each kind of error should map to the exit code the README documents for it.
+    fn test_exit_code() {
+        assert_eq!(Error::ConfigNotFound.exit_code(), exit_code::CONFIG);
+        assert_eq!(
+            Error::InvalidDuration("soon".into()).exit_code(),
+            exit_code::CONFIG
+        );
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(
+            Error::ConnectionError(Box::new(refused.into())).exit_code(),
+            exit_code::CONNECTION
+        );
+        assert!(is_auth_failure_code(&SqlState::INVALID_PASSWORD));
+        assert!(is_auth_failure_code(
+            &SqlState::INVALID_AUTHORIZATION_SPECIFICATION
+        ));
+        assert!(!is_auth_failure_code(&SqlState::CANNOT_CONNECT_NOW));
+        assert_eq!(
+            Error::DivergentMigration.exit_code(),
+            exit_code::PLAN_REFUSED
+        );
+        assert_eq!(Error::PlanNotConfirmed.exit_code(), exit_code::PLAN_REFUSED);
+        assert_eq!(
+            Error::MigrationDirNotFound.exit_code(),
+            exit_code::BAD_MIGRATIONS
+        );
+        assert_eq!(
+            Error::NotReversible(vec!["users".into()]).exit_code(),
+            exit_code::NOT_REVERSIBLE
+        );
+        assert_eq!(Error::Unknown.exit_code(), exit_code::OTHER);
+    }
+}
@@ -1,10 +1,32 @@
 use crate::errors::{Error, Result};
-use crate::migration::{Migration, MigrationBuilder};
+use crate::migration::{self, Migration, MigrationBuilder, NamingScheme};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::fs::File;
-use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// How a migration is laid out on disk.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// A `NAME/` folder holding `up.sql` and `down.sql`.
+    #[default]
+    Directory,
+    /// A single `NAME.sql` file split by `-- +movine Up` / `-- +movine Down` markers.
+    SingleFile,
+    /// `NAME.up.sql` and `NAME.down.sql` files side by side, as used by sqlx.
+    Flat,
+}
+
+const IGNORE_FILE: &str = ".movineignore";
+/// Squashed migrations are moved here. Being hidden, it is never scanned for migrations.
+const ARCHIVE_DIR: &str = ".archive";
+/// Repeatable migrations, one `NAME.sql` file each, live here.
+const REPEATABLE_DIR: &str = "repeatable";
+/// What follows a migration's name in the names of its files, for every layout.
+const LAYOUT_SUFFIXES: [&str; 4] = ["", ".sql", ".up.sql", ".down.sql"];
 
 pub struct FileHandler {
     migration_dir: PathBuf,
@@ -25,7 +47,50 @@ impl FileHandler {
         Ok(())
     }
 
-    pub fn write_migration(&self, migration: &Migration) -> Result<()> {
+    /// A handler for the archive of squashed migrations inside this migration directory.
+    pub fn archive(&self) -> Self {
+        Self {
+            migration_dir: self.migration_dir.join(ARCHIVE_DIR),
+        }
+    }
+
+    /// Moves the files of migration `name` into the archive, whatever their layout.
+    pub fn archive_migration(&self, name: &str) -> Result<()> {
+        let archive_dir = self.migration_dir.join(ARCHIVE_DIR);
+        fs::create_dir_all(&archive_dir)?;
+        for suffix in LAYOUT_SUFFIXES.iter() {
+            let file_name = format!("{}{}", name, suffix);
+            let path = self.migration_dir.join(&file_name);
+            if path.exists() {
+                fs::rename(&path, archive_dir.join(&file_name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames the files of migration `old`, whatever their layout. Returns whether any were
+    /// found.
+    pub fn rename_migration(&self, old: &str, new: &str) -> Result<bool> {
+        let mut renamed = false;
+        for suffix in LAYOUT_SUFFIXES.iter() {
+            let path = self.migration_dir.join(format!("{}{}", old, suffix));
+            if path.exists() {
+                fs::rename(&path, self.migration_dir.join(format!("{}{}", new, suffix)))?;
+                renamed = true;
+            }
+        }
+        Ok(renamed)
+    }
+
+    pub fn write_migration(&self, migration: &Migration, layout: Layout) -> Result<()> {
+        match layout {
+            Layout::Directory => self.write_directory_migration(migration),
+            Layout::SingleFile => self.write_single_file_migration(migration),
+            Layout::Flat => self.write_flat_migration(migration),
+        }
+    }
+
+    fn write_directory_migration(&self, migration: &Migration) -> Result<()> {
         let name = migration.name.clone().into();
         let folder: PathBuf = [&self.migration_dir, &name].iter().collect();
         fs::create_dir(&folder)?;
@@ -45,43 +110,516 @@ impl FileHandler {
         Ok(())
     }
 
+    fn write_single_file_migration(&self, migration: &Migration) -> Result<()> {
+        let path = self.migration_dir.join(format!("{}.sql", migration.name));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let contents = join_sections(
+            migration.up_sql.as_deref().unwrap_or(""),
+            Some(migration.down_sql.as_deref().unwrap_or("")),
+        );
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_flat_migration(&self, migration: &Migration) -> Result<()> {
+        let sections = [
+            ("up", migration.up_sql.as_deref()),
+            ("down", migration.down_sql.as_deref()),
+        ];
+        for (section, sql) in sections.iter() {
+            let path = self
+                .migration_dir
+                .join(format!("{}.{}.sql", migration.name, section));
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?;
+            file.write_all(sql.unwrap_or("").as_bytes())?;
+        }
+        Ok(())
+    }
+
     pub fn load_local_migrations(&self) -> Result<Vec<Migration>> {
         let directory = match fs::read_dir(&self.migration_dir) {
             Ok(dir) => dir,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
                 return Err(Error::MigrationDirNotFound);
             }
-            Err(e) => {
-                return Err(e.into());
-            }
+            Err(e) => return Err(load_error(&self.migration_dir, e)),
         };
+        let ignore_list = IgnoreList::load(&self.migration_dir.join(IGNORE_FILE))?;
         let mut migrations = Vec::new();
+        let mut flat_migrations: BTreeMap<String, FlatMigration> = BTreeMap::new();
 
         for entry in directory {
-            let entry = entry?;
-            let compound_name: String = entry.file_name().into_string().unwrap();
+            let entry = entry.map_err(|e| load_error(&self.migration_dir, e))?;
+            let path = entry.path();
+            let file_name = match entry.file_name().into_string() {
+                Ok(file_name) => file_name,
+                Err(_) => return Err(load_error(&path, "the file name is not valid UTF-8")),
+            };
 
-            let mut up_path = entry.path();
-            let mut down_path = entry.path();
-            up_path.push("up.sql");
-            down_path.push("down.sql");
+            if file_name.starts_with('.')
+                || file_name == REPEATABLE_DIR
+                || ignore_list.matches(&file_name)
+            {
+                debug!("Ignoring {}", path.display());
+                continue;
+            }
 
-            let mut file = File::open(up_path)?;
-            let mut up_sql = String::new();
-            file.read_to_string(&mut up_sql)?;
+            if path.is_dir() {
+                if let Some(migration) = load_directory_migration(&path, &file_name)? {
+                    migrations.push(migration);
+                }
+            } else if let Some(compound_name) = file_name.strip_suffix(".up.sql") {
+                flat_migrations
+                    .entry(compound_name.to_owned())
+                    .or_default()
+                    .up = Some(path);
+            } else if let Some(compound_name) = file_name.strip_suffix(".down.sql") {
+                flat_migrations
+                    .entry(compound_name.to_owned())
+                    .or_default()
+                    .down = Some(path);
+            } else if let Some(compound_name) = file_name.strip_suffix(".sql") {
+                if let Some(migration) = load_single_file_migration(&path, compound_name)? {
+                    migrations.push(migration);
+                }
+            } else {
+                debug!("Ignoring {}: not a migration", path.display());
+            }
+        }
 
-            let mut file = File::open(down_path)?;
-            let mut down_sql = String::new();
-            file.read_to_string(&mut down_sql)?;
+        for (compound_name, flat_migration) in flat_migrations {
+            migrations.push(flat_migration.load(&compound_name)?);
+        }
 
-            let migration = MigrationBuilder::new()
-                .compound_name(&compound_name)
-                .up_sql(&up_sql)
-                .down_sql(&down_sql)
-                .build()?;
-            migrations.push(migration);
+        let mut names = HashSet::new();
+        for migration in &migrations {
+            if !names.insert(&migration.name) {
+                return Err(Error::DuplicateMigration(migration.name.to_owned()));
+            }
         }
 
         Ok(migrations)
     }
+
+    /// Loads the `NAME.sql` files in the `repeatable` folder of the migration directory, sorted
+    /// by name. They have no down migration.
+    pub fn load_repeatable_migrations(&self) -> Result<Vec<Migration>> {
+        let dir = self.migration_dir.join(REPEATABLE_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        load_sql_files(&dir, "")
+    }
+}
+
+/// Loads the seeds in `<seeds_dir>/<env>`, named `<env>/NAME` after their `NAME.sql` files so
+/// that seeds of different environments are tracked apart.
+pub fn load_seeds(seeds_dir: &Path, env: &str) -> Result<Vec<Migration>> {
+    let dir = seeds_dir.join(env);
+    if !dir.is_dir() {
+        return Err(load_error(&dir, "there is no such seed directory"));
+    }
+    load_sql_files(&dir, &format!("{}/", env))
+}
+
+/// Loads every `NAME.sql` file in `dir` as the up migration of `<prefix>NAME`, sorted by name.
+fn load_sql_files(dir: &Path, prefix: &str) -> Result<Vec<Migration>> {
+    let mut migrations = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| load_error(dir, e))? {
+        let path = entry.map_err(|e| load_error(dir, e))?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) if file_name.starts_with('.') || !path.is_file() => continue,
+            Some(file_name) => file_name.strip_suffix(".sql"),
+            None => return Err(load_error(&path, "the file name is not valid UTF-8")),
+        };
+        match name {
+            Some(name) => migrations.push(
+                MigrationBuilder::new()
+                    .compound_name(&format!("{}{}", prefix, name))
+                    .up_sql(&read_sql(&path)?)
+                    .build()?,
+            ),
+            None => debug!("Ignoring {}: not a migration", path.display()),
+        }
+    }
+    migrations.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(migrations)
+}
+
+/// The files of a migration in the flat layout. The down file is optional.
+#[derive(Default)]
+struct FlatMigration {
+    up: Option<PathBuf>,
+    down: Option<PathBuf>,
+}
+
+impl FlatMigration {
+    fn load(&self, compound_name: &str) -> Result<Migration> {
+        let up_path = match (&self.up, &self.down) {
+            (Some(up_path), _) => up_path,
+            (None, Some(down_path)) => {
+                return Err(load_error(down_path, "there is no matching .up.sql file"));
+            }
+            (None, None) => unreachable!(),
+        };
+
+        let mut builder = MigrationBuilder::new();
+        builder
+            .compound_name(compound_name)
+            .up_sql(&read_sql(up_path)?);
+        if let Some(down_path) = &self.down {
+            builder.down_sql(&read_sql(down_path)?);
+        }
+        builder.build().map_err(|e| load_error(up_path, e))
+    }
+}
+
+/// Loads a `NAME/up.sql` + `NAME/down.sql` migration. Folders without an `up.sql` are not
+/// migrations and are skipped. A missing `down.sql` makes the migration irreversible.
+fn load_directory_migration(path: &Path, compound_name: &str) -> Result<Option<Migration>> {
+    let up_path = path.join("up.sql");
+    let down_path = path.join("down.sql");
+    if !up_path.exists() {
+        if down_path.exists() {
+            return Err(load_error(path, "it has a down.sql but no up.sql"));
+        }
+        debug!("Ignoring {}: no up.sql", path.display());
+        return Ok(None);
+    }
+
+    let mut builder = MigrationBuilder::new();
+    builder
+        .compound_name(compound_name)
+        .up_sql(&read_sql(&up_path)?);
+    if down_path.exists() {
+        builder.down_sql(&read_sql(&down_path)?);
+    }
+    let migration = builder.build().map_err(|e| load_error(path, e))?;
+    Ok(Some(migration))
+}
+
+/// Loads a `NAME.sql` single-file migration. Files without an `Up` marker, like the
+/// `schema.sql` written by `dump-schema`, are not migrations and are skipped, unless they are
+/// named like one, as then the marker is more likely mistyped.
+fn load_single_file_migration(path: &Path, compound_name: &str) -> Result<Option<Migration>> {
+    let contents = read_sql(path)?;
+    let (up_sql, down_sql) = match split_sections(&contents) {
+        Some(sections) => sections,
+        None if NamingScheme::detect(compound_name, NamingScheme::default()).is_some() => {
+            return Err(load_error(path, "there is no `-- +movine Up` marker"));
+        }
+        None => {
+            debug!("Ignoring {}: no `-- +movine Up` marker", path.display());
+            return Ok(None);
+        }
+    };
+
+    let mut builder = MigrationBuilder::new();
+    builder.compound_name(compound_name).up_sql(up_sql);
+    if let Some(down_sql) = down_sql {
+        builder.down_sql(down_sql);
+    }
+    let migration = builder.build().map_err(|e| load_error(path, e))?;
+    Ok(Some(migration))
+}
+
+fn read_sql(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| load_error(path, e))
+}
+
+fn load_error<T: ToString>(path: &Path, reason: T) -> Error {
+    Error::LoadMigration {
+        path: path.to_owned(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Glob patterns read from the `.movineignore` file in the migration directory. Entries whose
+/// name matches one of them are not treated as migrations.
+#[derive(Default)]
+struct IgnoreList {
+    patterns: Vec<String>,
+}
+
+impl IgnoreList {
+    fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(load_error(path, e)),
+        };
+
+        let patterns = contents
+            .lines()
+            .map(|line| line.trim().trim_end_matches('/'))
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_owned())
+            .collect();
+        Ok(Self { patterns })
+    }
+
+    fn matches(&self, file_name: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, file_name))
+    }
+}
+
+/// Matches `name` against a pattern where `*` matches any run of characters and `?` any single
+/// character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            n = matched + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Splits a single-file migration into its up and down sections. Each section is exactly the
+/// text between its marker line and the next marker, so it hashes the same as an `up.sql` and
+/// `down.sql` with that text. A missing down section hashes the same as an empty `down.sql`.
+/// Anything before the `Up` marker is ignored.
+pub fn split_sections(contents: &str) -> Option<(&str, Option<&str>)> {
+    let mut up = None;
+    let mut down = None;
+    let mut offset = 0;
+    for line in contents.split('\n') {
+        let line_end = (offset + line.len() + 1).min(contents.len());
+        match migration::parse_directive(line) {
+            Some(("Up", _)) if up.is_none() => up = Some((line_end, contents.len())),
+            Some(("Down", _)) if up.is_some() && down.is_none() => {
+                up = up.map(|(start, _)| (start, offset));
+                down = Some((line_end, contents.len()));
+            }
+            _ => {}
+        }
+        offset = line_end;
+    }
+
+    let (up_start, up_end) = up?;
+    Some((
+        &contents[up_start..up_end],
+        down.map(|(start, end)| &contents[start..end]),
+    ))
+}
+
+/// The inverse of `split_sections`. The `Down` marker has to start a line, so an up section
+/// that doesn't end in a newline gets one: `split_sections` gives back `up_sql` with a newline
+/// added in that case, and exactly `up_sql` otherwise.
+pub fn join_sections(up_sql: &str, down_sql: Option<&str>) -> String {
+    let mut contents = String::from("-- +movine Up\n");
+    contents.push_str(up_sql);
+    if let Some(down_sql) = down_sql {
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str("-- +movine Down\n");
+        contents.push_str(down_sql);
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads the only migration in a migration directory holding just `files`.
+    fn load_only_migration(files: &[(&str, &str)]) -> Migration {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let mut migrations = FileHandler::new(dir.path().to_str().unwrap())
+            .load_local_migrations()
+            .unwrap();
+        assert_eq!(migrations.len(), 1);
+        migrations.remove(0)
+    }
+
+    #[test]
+    /// Single-file sections should round-trip, adding only the newline the `Down` marker needs.
+    fn test_single_file_sections() {
+        let up_sql = "CREATE TABLE users (id int);\n";
+        let down_sql = "DROP TABLE users;\n";
+        let contents = join_sections(up_sql, Some(down_sql));
+        assert_eq!(split_sections(&contents), Some((up_sql, Some(down_sql))));
+        let contents = join_sections("SELECT 1;", Some(""));
+        assert_eq!(split_sections(&contents), Some(("SELECT 1;\n", Some(""))));
+        let contents = join_sections("SELECT 1;", None);
+        assert_eq!(split_sections(&contents), Some(("SELECT 1;", None)));
+        assert_eq!(
+            split_sections("-- +movine Up\nSELECT 1;\n"),
+            Some(("SELECT 1;\n", None))
+        );
+        assert_eq!(split_sections("SELECT 1;\n"), None);
+    }
+
+    #[test]
+    /// A migration should hash the same in the directory and single-file layouts, with or
+    /// without a down section.
+    fn test_single_file_hash_matches_directory() {
+        let up_sql = "CREATE TABLE users (id int);\n";
+        let down_sql = "DROP TABLE users;\n";
+        let directory = load_only_migration(&[
+            ("0001_users/up.sql", up_sql),
+            ("0001_users/down.sql", down_sql),
+        ]);
+        let single_file =
+            load_only_migration(&[("0001_users.sql", &join_sections(up_sql, Some(down_sql)))]);
+        assert_eq!(single_file.hash, directory.hash);
+
+        let directory =
+            load_only_migration(&[("0001_users/up.sql", up_sql), ("0001_users/down.sql", "")]);
+        for contents in &[join_sections(up_sql, None), join_sections(up_sql, Some(""))] {
+            let single_file = load_only_migration(&[("0001_users.sql", contents)]);
+            assert_eq!(single_file.hash, directory.hash);
+            assert!(!single_file.is_reversable());
+        }
+    }
+
+    #[test]
+    /// A flat migration should hash like the same directory migration, so switching layouts
+    /// doesn't turn migrations into variants.
+    fn test_flat_hash_matches_directory() {
+        let up_sql = "CREATE TABLE users (id int);\n";
+        let down_sql = "DROP TABLE users;\n";
+        let directory = load_only_migration(&[
+            ("0001_users/up.sql", up_sql),
+            ("0001_users/down.sql", down_sql),
+        ]);
+        let flat = load_only_migration(&[
+            ("0001_users.up.sql", up_sql),
+            ("0001_users.down.sql", down_sql),
+        ]);
+        assert_eq!(flat.hash, directory.hash);
+        assert!(flat.is_reversable());
+
+        let directory =
+            load_only_migration(&[("0001_users/up.sql", up_sql), ("0001_users/down.sql", "")]);
+        let flat = load_only_migration(&[("0001_users.up.sql", up_sql)]);
+        assert_eq!(flat.hash, directory.hash);
+        assert!(!flat.is_reversable());
+    }
+
+    #[test]
+    /// A directory migration without a `down.sql` should hash like one with an empty `down.sql`.
+    fn test_directory_without_down_hash() {
+        let up_sql = "CREATE TABLE users (id int);\n";
+        let with_down =
+            load_only_migration(&[("0001_users/up.sql", up_sql), ("0001_users/down.sql", "")]);
+        let without_down = load_only_migration(&[("0001_users/up.sql", up_sql)]);
+        assert_eq!(without_down.hash, with_down.hash);
+        assert!(!without_down.is_reversable());
+    }
+
+    #[test]
+    /// Top-level `.sql` files without an `Up` marker should be skipped, not fail loading, unless
+    /// they are named like a migration.
+    fn test_skip_sql_without_up_marker() {
+        let migration = load_only_migration(&[
+            (
+                "0001_users.sql",
+                "-- +movine Up\nCREATE TABLE users (id int);\n",
+            ),
+            ("schema.sql", "CREATE TABLE users (id int);\n"),
+            ("notes.sql", "-- TODO: add an index\n"),
+        ]);
+        assert_eq!(migration.name, "0001_users");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0002_posts.sql");
+        fs::write(&path, "-- +movine up\nCREATE TABLE posts (id int);\n").unwrap();
+        let file_handler = FileHandler::new(dir.path().to_str().unwrap());
+        match file_handler.load_local_migrations() {
+            Err(Error::LoadMigration {
+                path: error_path, ..
+            }) => assert_eq!(error_path, path),
+            result => panic!("expected a load error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    /// Repeatable migrations should be loaded from `repeatable/NAME.sql`, sorted by name and
+    /// without a down migration, and not count as versioned migrations.
+    fn test_load_repeatable_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let repeatable_dir = dir.path().join(REPEATABLE_DIR);
+        fs::create_dir(&repeatable_dir).unwrap();
+        fs::write(
+            repeatable_dir.join("views.sql"),
+            "CREATE VIEW v AS SELECT 1;",
+        )
+        .unwrap();
+        fs::write(repeatable_dir.join("functions.sql"), "SELECT 1;").unwrap();
+        fs::write(repeatable_dir.join("README.md"), "Not a migration").unwrap();
+        let file_handler = FileHandler::new(dir.path().to_str().unwrap());
+
+        let repeatables = file_handler.load_repeatable_migrations().unwrap();
+        let names: Vec<_> = repeatables.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["functions", "views"]);
+        assert!(repeatables.iter().all(|m| !m.is_reversable()));
+        assert!(file_handler.load_local_migrations().unwrap().is_empty());
+
+        let empty = tempfile::tempdir().unwrap();
+        let file_handler = FileHandler::new(empty.path().to_str().unwrap());
+        assert!(file_handler
+            .load_repeatable_migrations()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    /// `.movineignore` patterns should support `*` and `?` wildcards.
+    fn test_glob_match() {
+        assert!(glob_match("README*", "README.md"));
+        assert!(glob_match("*.swp", ".up.sql.swp"));
+        assert!(glob_match("draft_?", "draft_1"));
+        assert!(glob_match("*_wip_*", "2020-01-01-000000_wip_users"));
+        assert!(!glob_match("*.swp", "up.sql"));
+        assert!(!glob_match("draft_?", "draft_12"));
+    }
+
+    #[test]
+    /// Seeds should be loaded from `<seeds_dir>/<env>` in order and named after their
+    /// environment, and a missing environment should be an error.
+    fn test_load_seeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let dev_dir = dir.path().join("dev");
+        fs::create_dir(&dev_dir).unwrap();
+        fs::write(dev_dir.join("02_orders.sql"), "SELECT 2;").unwrap();
+        fs::write(dev_dir.join("01_users.sql"), "SELECT 1;").unwrap();
+        fs::write(dev_dir.join("notes.txt"), "Not a seed").unwrap();
+
+        let seeds = load_seeds(dir.path(), "dev").unwrap();
+        let names: Vec<_> = seeds.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["dev/01_users", "dev/02_orders"]);
+        assert!(seeds.iter().all(|m| !m.is_reversable()));
+        assert!(matches!(
+            load_seeds(dir.path(), "prod"),
+            Err(Error::LoadMigration { .. })
+        ));
+    }
 }
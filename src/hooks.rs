@@ -0,0 +1,259 @@
+use crate::adaptor::DbAdaptor;
+use crate::display;
+use crate::errors::{Error, Result};
+use crate::migration::Migration;
+use crate::plan_builder::Step;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// When a hook runs while a migration plan is run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HookPoint {
+    BeforeAll,
+    AfterAll,
+    BeforeEach,
+    AfterEach,
+}
+
+impl HookPoint {
+    pub const ALL: [HookPoint; 4] = [
+        HookPoint::BeforeAll,
+        HookPoint::AfterAll,
+        HookPoint::BeforeEach,
+        HookPoint::AfterEach,
+    ];
+
+    /// The name of the hook's SQL file (without `.sql`) and of its key in `[hooks]`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookPoint::BeforeAll => "before_all",
+            HookPoint::AfterAll => "after_all",
+            HookPoint::BeforeEach => "before_each",
+            HookPoint::AfterEach => "after_each",
+        }
+    }
+}
+
+/// The `[hooks]` section of `movine.toml`: shell commands to run around migrations.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HookCommands {
+    pub before_all: Option<String>,
+    pub after_all: Option<String>,
+    pub before_each: Option<String>,
+    pub after_each: Option<String>,
+}
+
+impl HookCommands {
+    fn get(&self, point: HookPoint) -> Option<&str> {
+        match point {
+            HookPoint::BeforeAll => self.before_all.as_deref(),
+            HookPoint::AfterAll => self.after_all.as_deref(),
+            HookPoint::BeforeEach => self.before_each.as_deref(),
+            HookPoint::AfterEach => self.after_each.as_deref(),
+        }
+    }
+}
+
+/// SQL and shell commands run before and after a migration plan and each of its steps. The
+/// `*_all` hooks only run if the plan has any steps.
+#[derive(Debug, Default, Clone)]
+pub struct Hooks {
+    sql: Vec<(HookPoint, String)>,
+    commands: HookCommands,
+}
+
+impl Hooks {
+    /// Reads the `<point>.sql` files in `dir`, if there are any, to run along with `commands`.
+    pub fn load(dir: &Path, commands: &HookCommands) -> Result<Self> {
+        let mut sql = Vec::new();
+        for &point in &HookPoint::ALL {
+            let path = dir.join(format!("{}.sql", point.as_str()));
+            if path.is_file() {
+                sql.push((point, fs::read_to_string(&path)?));
+            }
+        }
+        Ok(Self {
+            sql,
+            commands: commands.clone(),
+        })
+    }
+
+    /// Runs the SQL hook for `point` and then its shell command, printing each unless `quiet`.
+    /// `step` is the step the `*_each` hooks run around, which shell commands get in
+    /// `MOVINE_MIGRATION` and `MOVINE_STEP`.
+    pub fn run<A: DbAdaptor + ?Sized>(
+        &self,
+        adaptor: &mut A,
+        point: HookPoint,
+        step: Option<(Step, &Migration)>,
+        quiet: bool,
+    ) -> Result<()> {
+        for (_, sql) in self.sql.iter().filter(|(p, _)| *p == point) {
+            let hook = format!("{}.sql", point.as_str());
+            if !quiet {
+                display::print_hook(&hook);
+            }
+            adaptor
+                .execute_sql(sql)
+                .map_err(|e| hook_failed(&hook, e.to_string()))?;
+        }
+
+        if let Some(command) = self.commands.get(point) {
+            if !quiet {
+                display::print_hook(command);
+            }
+            let mut shell = shell(command);
+            shell.env("MOVINE_HOOK", point.as_str());
+            if let Some((step, migration)) = step {
+                shell
+                    .env("MOVINE_MIGRATION", &migration.name)
+                    .env("MOVINE_STEP", format!("{:?}", step).to_lowercase());
+            }
+            let status = shell
+                .status()
+                .map_err(|e| hook_failed(command, e.to_string()))?;
+            if !status.success() {
+                let reason = match status.code() {
+                    Some(code) => format!("it exited with code {}", code),
+                    None => "it was killed by a signal".to_owned(),
+                };
+                return Err(hook_failed(command, reason));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hook_failed(hook: &str, reason: String) -> Error {
+    Error::HookFailed {
+        hook: hook.to_owned(),
+        reason,
+    }
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptor::RunOptions;
+    use crate::migration::MigrationBuilder;
+    use rusqlite::Connection;
+
+    fn migration(name: &str, up_sql: &str) -> Migration {
+        MigrationBuilder::new()
+            .compound_name(name)
+            .up_sql(up_sql)
+            .down_sql("")
+            .build()
+            .unwrap()
+    }
+
+    fn run(hooks: Hooks, conn: &mut Connection, migrations: &[Migration]) -> Result<()> {
+        let plan: Vec<_> = migrations.iter().map(|m| (Step::Up, m)).collect();
+        let options = RunOptions {
+            quiet: true,
+            hooks,
+            ..RunOptions::default()
+        };
+        conn.run_migration_plan(&plan, &options)
+    }
+
+    #[test]
+    /// Only the `<point>.sql` files of the hooks directory should be loaded.
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("before_all.sql"), "SELECT 1;").unwrap();
+        fs::write(dir.path().join("after_each.sql"), "SELECT 2;").unwrap();
+        fs::write(dir.path().join("notes.sql"), "SELECT 3;").unwrap();
+        let hooks = Hooks::load(dir.path(), &HookCommands::default()).unwrap();
+        assert_eq!(
+            hooks.sql,
+            vec![
+                (HookPoint::BeforeAll, "SELECT 1;".to_owned()),
+                (HookPoint::AfterEach, "SELECT 2;".to_owned()),
+            ]
+        );
+
+        let missing = dir.path().join("missing");
+        assert!(Hooks::load(&missing, &HookCommands::default())
+            .unwrap()
+            .sql
+            .is_empty());
+    }
+
+    #[test]
+    /// `before_each.sql` should run before every step of the plan.
+    fn test_sql_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("before_each.sql"),
+            "INSERT INTO hook_log VALUES (1);",
+        )
+        .unwrap();
+        let hooks = Hooks::load(dir.path(), &HookCommands::default()).unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(conn.init_up_sql()).unwrap();
+        conn.execute_batch("CREATE TABLE hook_log (x int);")
+            .unwrap();
+
+        let migrations = [
+            migration("0001_a", "CREATE TABLE a (x int);"),
+            migration("0002_b", "CREATE TABLE b (x int);"),
+        ];
+        run(hooks, &mut conn, &migrations).unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM hook_log",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    /// A failing shell hook should fail with `HookFailed` and stop the plan.
+    fn test_failing_command() {
+        let commands = HookCommands {
+            after_each: Some("test \"$MOVINE_MIGRATION\" != 0002_b".to_owned()),
+            ..HookCommands::default()
+        };
+        let hooks = Hooks::load(Path::new("no-hooks"), &commands).unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(conn.init_up_sql()).unwrap();
+
+        let migrations = [
+            migration("0001_a", "CREATE TABLE a (x int);"),
+            migration("0002_b", "CREATE TABLE b (x int);"),
+            migration("0003_c", "CREATE TABLE c (x int);"),
+        ];
+        match run(hooks, &mut conn, &migrations) {
+            Err(Error::HookFailed { reason, .. }) => assert_eq!(reason, "it exited with code 1"),
+            _ => panic!("expected the hook to fail"),
+        }
+        let applied: Vec<_> = conn
+            .load_migrations()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert!(!applied.contains(&"0003_c".to_owned()));
+    }
+}
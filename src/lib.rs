@@ -31,6 +31,11 @@
 //!
 //! ```
 use chrono::prelude::*;
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[macro_use]
 extern crate log;
@@ -40,25 +45,63 @@ pub mod config;
 mod display;
 pub mod errors;
 mod file_handler;
+mod hooks;
+mod lint;
 mod match_maker;
 mod migration;
 mod plan_builder;
+mod policy;
+pub mod schema;
+pub mod scratch;
+mod sql;
+mod template;
 
 pub use adaptor::DbAdaptor;
+use adaptor::{RetryPolicy, RunOptions};
 pub use config::Config;
 use errors::{Error, Result};
 use file_handler::FileHandler;
-use migration::MigrationBuilder;
-use plan_builder::PlanBuilder;
+pub use file_handler::Layout;
+pub use hooks::HookCommands;
+use hooks::Hooks;
+pub use lint::LintConfig;
+pub use migration::NamingScheme;
+use migration::{Migration, MigrationBuilder};
+use plan_builder::{PlanBuilder, Step};
+pub use policy::Policy;
+use policy::Violation;
+use schema::Schema;
+use template::Template;
+
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// The environment `seed` uses when neither `--env` nor the database's environment is set.
+const DEFAULT_SEED_ENVIRONMENT: &str = "dev";
 
 pub struct Movine<T> {
     adaptor: T,
     migration_dir: String,
+    templates_dir: String,
+    template: Option<String>,
+    naming: NamingScheme,
+    layout: Layout,
     number: Option<usize>,
     show_plan: bool,
     ignore_divergent: bool,
     ignore_unreversable: bool,
     strict: bool,
+    lock_timeout: Option<Duration>,
+    statement_timeout: Option<Duration>,
+    run_options: RunOptions,
+    dump_schema: Option<PathBuf>,
+    lint_disabled: Vec<String>,
+    policy: Policy,
+    description: Option<String>,
+    hooks_dir: String,
+    hook_commands: HookCommands,
+    seeds_dir: String,
+    environment: Option<String>,
+    protected_environments: Vec<String>,
+    confirm: bool,
 }
 
 impl<T: DbAdaptor> Movine<T> {
@@ -66,12 +109,122 @@ impl<T: DbAdaptor> Movine<T> {
         Self {
             adaptor,
             migration_dir: "./migrations".into(),
+            templates_dir: "./templates".into(),
+            template: None,
+            naming: NamingScheme::default(),
+            layout: Layout::default(),
             number: None,
             show_plan: false,
             ignore_divergent: false,
             ignore_unreversable: false,
             strict: false,
+            lock_timeout: None,
+            statement_timeout: None,
+            run_options: RunOptions::default(),
+            dump_schema: None,
+            lint_disabled: Vec::new(),
+            policy: Policy::default(),
+            description: None,
+            hooks_dir: "./hooks".into(),
+            hook_commands: HookCommands::default(),
+            seeds_dir: "./seeds".into(),
+            environment: None,
+            protected_environments: Vec::new(),
+            confirm: false,
+        }
+    }
+
+    /// Applies the settings from a loaded `Config` (everything except the connection details).
+    pub fn configure(&mut self, config: &Config) -> &mut Self {
+        let retry = config.migration_retries.map(|retries| RetryPolicy {
+            retries,
+            backoff: config
+                .migration_retry_backoff
+                .unwrap_or(DEFAULT_RETRY_BACKOFF),
+        });
+        self.set_lock_timeout(config.lock_timeout)
+            .set_statement_timeout(config.statement_timeout)
+            .set_retry_policy(retry);
+        if let Some(templates_dir) = &config.templates_dir {
+            self.set_templates_dir(templates_dir);
+        }
+        if let Some(hooks_dir) = &config.hooks_dir {
+            self.set_hooks_dir(hooks_dir);
         }
+        if let Some(seeds_dir) = &config.seeds_dir {
+            self.set_seeds_dir(seeds_dir);
+        }
+        let protected: Vec<_> = config
+            .environments
+            .iter()
+            .filter(|(_, environment)| environment.protected)
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        self.set_template(config.default_template.as_deref())
+            .set_naming(config.naming)
+            .set_layout(config.layout)
+            .set_lint_disabled(&config.lint.disabled)
+            .set_policy(&config.policy)
+            .set_hook_commands(&config.hooks)
+            .set_environment(config.environment.as_deref())
+            .set_protected_environments(&protected)
+    }
+
+    /// Names of the rules `lint` should not check.
+    pub fn set_lint_disabled(&mut self, rules: &[String]) -> &mut Self {
+        self.lint_disabled = rules.to_vec();
+        self
+    }
+
+    /// Rules that `generate`, `check` and `up` hold the migrations to.
+    pub fn set_policy(&mut self, policy: &Policy) -> &mut Self {
+        self.policy = policy.clone();
+        self
+    }
+
+    /// Description `generate` puts in a `-- +movine description` directive of the new migration.
+    pub fn set_description(&mut self, description: Option<&str>) -> &mut Self {
+        self.description = description.map(|x| x.to_owned());
+        self
+    }
+
+    /// Directory holding the `before_all.sql`, `after_all.sql`, `before_each.sql` and
+    /// `after_each.sql` hooks.
+    pub fn set_hooks_dir(&mut self, hooks_dir: &str) -> &mut Self {
+        self.hooks_dir = hooks_dir.into();
+        self
+    }
+
+    /// Directory holding a folder of seeds for each environment.
+    pub fn set_seeds_dir(&mut self, seeds_dir: &str) -> &mut Self {
+        self.seeds_dir = seeds_dir.into();
+        self
+    }
+
+    /// The environment the database belongs to, which `seed` uses by default.
+    pub fn set_environment(&mut self, environment: Option<&str>) -> &mut Self {
+        self.environment = environment.map(|x| x.to_owned());
+        self
+    }
+
+    /// Environments that `seed` refuses to run against, and whose migration plans need to be
+    /// confirmed.
+    pub fn set_protected_environments(&mut self, environments: &[String]) -> &mut Self {
+        self.protected_environments = environments.to_vec();
+        self
+    }
+
+    /// Ask on the terminal before running a plan that rolls back migrations or migrates a
+    /// protected environment. Without a terminal to ask on, such plans are refused.
+    pub fn set_confirm(&mut self, confirm: bool) -> &mut Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Shell commands to run around migrations, alongside the SQL hooks.
+    pub fn set_hook_commands(&mut self, commands: &HookCommands) -> &mut Self {
+        self.hook_commands = commands.clone();
+        self
     }
 
     pub fn set_migration_dir(&mut self, migration_dir: &str) -> &mut Self {
@@ -79,6 +232,29 @@ impl<T: DbAdaptor> Movine<T> {
         self
     }
 
+    pub fn set_templates_dir(&mut self, templates_dir: &str) -> &mut Self {
+        self.templates_dir = templates_dir.into();
+        self
+    }
+
+    /// Template used by `generate`, looked up in the templates directory.
+    pub fn set_template(&mut self, template: Option<&str>) -> &mut Self {
+        self.template = template.map(|x| x.to_owned());
+        self
+    }
+
+    /// Naming scheme for the migrations created by `init` and `generate`.
+    pub fn set_naming(&mut self, naming: NamingScheme) -> &mut Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Layout of the migrations created by `init` and `generate`.
+    pub fn set_layout(&mut self, layout: Layout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
     pub fn set_number(&mut self, number: Option<usize>) -> &mut Self {
         self.number = number;
         self
@@ -89,6 +265,12 @@ impl<T: DbAdaptor> Movine<T> {
         self
     }
 
+    /// File that `up` writes the resulting schema to after migrating.
+    pub fn set_dump_schema(&mut self, path: Option<&Path>) -> &mut Self {
+        self.dump_schema = path.map(|x| x.to_owned());
+        self
+    }
+
     pub fn set_ignore_divergent(&mut self, ignore_divergent: bool) -> &mut Self {
         self.ignore_divergent = ignore_divergent;
         self
@@ -104,6 +286,24 @@ impl<T: DbAdaptor> Movine<T> {
         self
     }
 
+    /// Default `lock_timeout` for migrations that don't set their own.
+    pub fn set_lock_timeout(&mut self, lock_timeout: Option<Duration>) -> &mut Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Default `statement_timeout` for migrations that don't set their own.
+    pub fn set_statement_timeout(&mut self, statement_timeout: Option<Duration>) -> &mut Self {
+        self.statement_timeout = statement_timeout;
+        self
+    }
+
+    /// Retry migrations that fail on lock timeouts, deadlocks or serialization failures.
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) -> &mut Self {
+        self.run_options.retry = retry;
+        self
+    }
+
     pub fn initialize(&mut self) -> Result<()> {
         let file_handler = FileHandler::new(&self.migration_dir);
         file_handler.create_migration_directory()?;
@@ -113,11 +313,13 @@ impl<T: DbAdaptor> Movine<T> {
         let init_migration = MigrationBuilder::new()
             .name("movine_init")
             .date(Utc.timestamp_opt(0, 0).unwrap())
+            .naming(self.naming)
+            .sequence(0)
             .up_sql(up_sql)
             .down_sql(down_sql)
             .build()?;
 
-        match file_handler.write_migration(&init_migration) {
+        match file_handler.write_migration(&init_migration, self.layout) {
             Ok(_) => {}
             Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
             x => x?,
@@ -125,63 +327,461 @@ impl<T: DbAdaptor> Movine<T> {
 
         // Can't just call to `up` function since we are unable to get
         // database migrations until we run this migration.
-        let local_migrations = file_handler.load_local_migrations()?;
+        let mut local_migrations = file_handler.load_local_migrations()?;
+        migration::check_naming_schemes(
+            local_migrations.iter().map(|m| m.name.as_str()),
+            self.naming,
+        )?;
+        self.apply_default_timeouts(&mut local_migrations);
         let db_migrations = Vec::new();
         let plan = PlanBuilder::new()
             .local_migrations(&local_migrations)
             .db_migrations(&db_migrations)
             .count(Some(1)) // Just want to run a single migration (the init one)
             .up()?;
-        self.adaptor.run_migration_plan(&plan)
+        self.adaptor.run_migration_plan(&plan, &self.run_options)
     }
 
     pub fn generate(&mut self, name: &str) -> Result<()> {
+        if self.policy.require_description && self.description.is_none() {
+            return Err(Error::PolicyViolations(vec![Violation {
+                migration: name.to_owned(),
+                reason: "needs a description, given with --description".to_owned(),
+            }]));
+        }
         let file_handler = FileHandler::new(&self.migration_dir);
-        let new_migration = MigrationBuilder::new()
+        let local_migrations = file_handler.load_local_migrations()?;
+        let sequence = local_migrations
+            .iter()
+            .filter(|m| {
+                NamingScheme::detect(&m.name, self.naming) == Some(NamingScheme::Sequential)
+            })
+            .filter_map(|m| migration::numeric_version(&m.name))
+            .max()
+            .map_or(0, |x| x + 1);
+
+        let date = Utc::now();
+        let mut builder = MigrationBuilder::new();
+        builder
             .name(name)
-            .date(Utc::now())
-            .build()?;
-        file_handler.write_migration(&new_migration)
+            .date(date)
+            .naming(self.naming)
+            .sequence(sequence);
+        let (mut up_sql, down_sql) = match &self.template {
+            Some(template) => Template::load(&self.templates_dir, template)?.render(name, date),
+            None => (String::new(), String::new()),
+        };
+        if let Some(description) = &self.description {
+            up_sql.insert_str(0, &format!("-- +movine description {}\n", description));
+        }
+        if !up_sql.is_empty() || !down_sql.is_empty() {
+            builder.up_sql(&up_sql).down_sql(&down_sql);
+        }
+        let new_migration = builder.build()?;
+
+        let names = local_migrations.iter().chain(Some(&new_migration));
+        migration::check_naming_schemes(names.map(|m| m.name.as_str()), self.naming)?;
+        file_handler.write_migration(&new_migration, self.layout)
+    }
+
+    /// Checks the local migrations against the `[policy]`, reporting every violation at once.
+    pub fn check(&mut self) -> Result<()> {
+        let (local_migrations, db_migrations) = self.load_migrations()?;
+        self.check_policy(&local_migrations, &db_migrations)?;
+        display::print_policy_ok(local_migrations.len());
+        Ok(())
     }
 
     pub fn status(&mut self) -> Result<()> {
-        let file_handler = FileHandler::new(&self.migration_dir);
-        let local_migrations = file_handler.load_local_migrations()?;
-        let db_migrations = self.adaptor.load_migrations()?;
+        let (local_migrations, db_migrations) = self.load_migrations()?;
 
         let status = PlanBuilder::new()
             .local_migrations(&local_migrations)
             .db_migrations(&db_migrations)
             .status()?;
 
+        let repeatables = self.load_repeatable_migrations()?;
+        let db_repeatables = self.adaptor.load_repeatable_migrations()?;
+        let outdated = outdated_repeatables(&repeatables, &db_repeatables);
+        let repeatable_status: Vec<_> = repeatables
+            .iter()
+            .map(|m| (m.name.as_str(), !outdated.contains(&m)))
+            .collect();
+
+        display::print_repeatable_status(&repeatable_status);
         display::print_status(&status);
+        display::print_renames(&match_maker::find_renames(&status));
         Ok(())
     }
 
     pub fn up(&mut self) -> Result<()> {
-        let file_handler = FileHandler::new(&self.migration_dir);
-        let local_migrations = file_handler.load_local_migrations()?;
-        let db_migrations = self.adaptor.load_migrations()?;
+        self.run_up(None)
+    }
 
-        let plan = PlanBuilder::new()
+    /// Like `up`, but first checks the migrations on `shadow`, an empty database. Before the
+    /// database is touched, the migrations already applied to it are replayed on the shadow
+    /// database and the two schemas compared, then the pending migrations are run there. Only
+    /// if both succeed are they run on the database. Its schema is then compared with the
+    /// shadow database's once more; drift found by this last check is reported after the
+    /// database has been migrated, and isn't rolled back. Drift fails with `Error::SchemaDrift`.
+    pub fn up_verified(&mut self, shadow: &mut dyn DbAdaptor) -> Result<()> {
+        self.run_up(Some(shadow))
+    }
+
+    fn run_up(&mut self, shadow: Option<&mut dyn DbAdaptor>) -> Result<()> {
+        let (local_migrations, db_migrations) = self.load_migrations()?;
+        self.check_policy(&local_migrations, &db_migrations)?;
+        let repeatables = self.load_repeatable_migrations()?;
+        let db_repeatables = self.adaptor.load_repeatable_migrations()?;
+
+        let mut plan = PlanBuilder::new()
             .local_migrations(&local_migrations)
             .db_migrations(&db_migrations)
             .count(self.number)
             .set_strict(self.strict)
             .up()?;
+        // Repeatables may depend on any versioned migration, so only follow a complete `up`.
+        if self.number.is_none() {
+            plan.extend(
+                outdated_repeatables(&repeatables, &db_repeatables)
+                    .into_iter()
+                    .map(|m| (Step::Repeat, m)),
+            );
+        }
 
         if self.show_plan {
             display::print_plan(&plan);
+            return Ok(());
+        }
+        let shown = self.confirm_plan(&plan)?;
+        match shadow {
+            Some(shadow) => {
+                let options = RunOptions {
+                    quiet: true,
+                    ..self.run_options.clone()
+                };
+                let no_migrations = Vec::new();
+                let applied: Vec<_> = PlanBuilder::new()
+                    .local_migrations(&local_migrations)
+                    .db_migrations(&no_migrations)
+                    .up()?
+                    .into_iter()
+                    .filter(|(_, m)| db_migrations.iter().any(|d| d.name == m.name))
+                    .chain(db_repeatables.iter().map(|m| (Step::Repeat, m)))
+                    .collect();
+                shadow.run_migration_plan(&applied, &options)?;
+                self.drift(&shadow.dump_schema()?)?;
+
+                display::print_shadow(plan.len());
+                shadow.run_migration_plan(&plan, &options)?;
+                self.adaptor
+                    .run_migration_plan(&plan, &self.plan_options(shown)?)?;
+                // Too late to stop the migrations, but still worth failing on: they behaved
+                // differently on the two databases.
+                self.drift(&shadow.dump_schema()?)?;
+            }
+            None => self
+                .adaptor
+                .run_migration_plan(&plan, &self.plan_options(shown)?)?,
+        }
+        if let Some(path) = self.dump_schema.clone() {
+            self.dump_schema(Some(&path))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the database schema to `output`, or to stdout.
+    pub fn dump_schema(&mut self, output: Option<&Path>) -> Result<()> {
+        let schema = self.adaptor.dump_schema()?;
+        match output {
+            Some(path) => fs::write(path, schema.to_string())?,
+            None => print!("{}", schema),
+        }
+        Ok(())
+    }
+
+    /// Builds the schema that the local migrations produce by running all of them on
+    /// `scratch`, which should be an empty database.
+    pub fn local_schema<S: DbAdaptor>(&mut self, mut scratch: S) -> Result<Schema> {
+        let local_migrations = self.load_local_migrations()?;
+        let repeatables = self.load_repeatable_migrations()?;
+        let db_migrations = Vec::new();
+        let mut plan = PlanBuilder::new()
+            .local_migrations(&local_migrations)
+            .db_migrations(&db_migrations)
+            .up()?;
+        plan.extend(repeatables.iter().map(|m| (Step::Repeat, m)));
+        let options = RunOptions {
+            quiet: true,
+            ..self.run_options.clone()
+        };
+        scratch.run_migration_plan(&plan, &options)?;
+        scratch.dump_schema()
+    }
+
+    /// Replaces the local migrations up to and including `up_to` with a single baseline
+    /// migration named after `up_to`'s version, and moves them to the `.archive` folder of the
+    /// migration directory. The init migration is kept. The baseline concatenates the squashed
+    /// migrations or, given a scratch database to run them on, recreates the schema they
+    /// produce.
+    ///
+    /// Databases that have the squashed migrations applied get their `movine_migrations` rows
+    /// replaced by the baseline's. Running `squash` again once the files are squashed, e.g.
+    /// against another database, only does this update. A database that has only some of the
+    /// squashed migrations applied is refused before any file is changed.
+    pub fn squash(&mut self, up_to: &str, scratch: Option<&mut dyn DbAdaptor>) -> Result<()> {
+        let file_handler = FileHandler::new(&self.migration_dir);
+        let local_migrations = self.load_local_migrations()?;
+        let baseline_name = format!("{}_baseline", migration::version(up_to));
+        let squash_files = local_migrations.iter().any(|m| m.name == up_to);
+
+        let squashed: Vec<String> = if squash_files {
+            squashable(&local_migrations, up_to)
+                .iter()
+                .map(|m| m.name.to_owned())
+                .collect()
+        } else {
+            let archived = match file_handler.archive().load_local_migrations() {
+                Err(Error::MigrationDirNotFound) => Vec::new(),
+                archived => archived?,
+            };
+            if !archived.iter().any(|m| m.name == up_to) {
+                return Err(Error::MigrationNotFound(up_to.to_owned()));
+            }
+            self.find_local_migration(&baseline_name)?;
+            squashable(&archived, up_to)
+                .iter()
+                .map(|m| m.name.to_owned())
+                .collect()
+        };
+
+        // Refuse before touching any files: the database must have all of the squashed
+        // migrations applied or none of them, as the baseline replaces them as a whole.
+        let db_names: Vec<String> = self
+            .adaptor
+            .load_migrations()?
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        let applied: Vec<String> = squashed
+            .iter()
+            .filter(|name| db_names.contains(name))
+            .cloned()
+            .collect();
+        if !applied.is_empty() && applied.len() < squashed.len() {
+            return Err(Error::SquashPartiallyApplied(up_to.to_owned()));
+        }
+
+        if squash_files {
+            let squashed = squashable(&local_migrations, up_to);
+            let baseline = build_baseline(&baseline_name, &local_migrations, &squashed, scratch)?;
+            file_handler.write_migration(&baseline, self.layout)?;
+            for migration in &squashed {
+                file_handler.archive_migration(&migration.name)?;
+            }
+            display::print_squash(&baseline_name, squashed.len());
+        }
+
+        if applied.is_empty() {
+            return Ok(());
+        }
+        // Reloaded from disk, so that the recorded hash is the one of the baseline's files.
+        let baseline = self.find_local_migration(&baseline_name)?;
+        self.adaptor.replace_migrations(&applied, &baseline)?;
+        display::print_squash_database(&baseline.name, applied.len());
+        Ok(())
+    }
+
+    /// Renames migration `old` to `new`, both its files and its row in `movine_migrations` if
+    /// it has been applied. If the files were already renamed by hand, only the database is
+    /// updated. The files are renamed back if the database can't be updated.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        migration::check_name(new, self.naming)?;
+        let file_handler = FileHandler::new(&self.migration_dir);
+        let local_migrations = self.load_local_migrations()?;
+        let local_names: Vec<&str> = local_migrations.iter().map(|m| m.name.as_str()).collect();
+        let db_names: Vec<String> = self
+            .adaptor
+            .load_migrations()?
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+
+        let applied = db_names.iter().any(|n| n == old);
+        let rename_files = local_names.contains(&old);
+        let renamed_by_hand = !rename_files && applied && local_names.contains(&new);
+        if rename_files && local_names.contains(&new) {
+            return Err(Error::MigrationExists(new.to_owned()));
+        }
+        if !rename_files && !renamed_by_hand {
+            return Err(Error::MigrationNotFound(old.to_owned()));
+        }
+        if db_names.iter().any(|n| n == new) {
+            return Err(Error::MigrationExists(new.to_owned()));
+        }
+        let names = local_names
+            .iter()
+            .map(|&name| if name == old { new } else { name });
+        migration::check_naming_schemes(names, self.naming)?;
+
+        if rename_files {
+            file_handler.rename_migration(old, new)?;
+        }
+        if applied {
+            if let Err(e) = self.adaptor.rename_migration(old, new) {
+                if rename_files {
+                    file_handler.rename_migration(new, old)?;
+                }
+                return Err(e);
+            }
+        }
+        display::print_rename(old, new);
+        Ok(())
+    }
+
+    /// Checks the pending migrations for operations that are risky to run against a live
+    /// database, such as dropping a column or adding a `NOT NULL` column without a default.
+    /// Fails with `Error::LintFailed` if any are found.
+    pub fn lint(&mut self) -> Result<()> {
+        let disabled = self
+            .lint_disabled
+            .iter()
+            .map(|name| lint::Rule::parse(name))
+            .collect::<Result<Vec<_>>>()?;
+        let (mut local_migrations, db_migrations) = self.load_migrations()?;
+        local_migrations.retain(|m| !db_migrations.iter().any(|d| d.name == m.name));
+        local_migrations.sort_by(|a, b| migration::compare_names(&a.name, &b.name));
+
+        let dialect = self.adaptor.dialect();
+        let mut count = 0;
+        for migration in &local_migrations {
+            for warning in lint::lint(migration, dialect, &disabled)? {
+                display::print_lint_warning(&migration.name, &warning);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            return Err(Error::LintFailed(count));
+        }
+        display::print_lint_clean(local_migrations.len());
+        Ok(())
+    }
+
+    /// Applies every local migration in order, stopping at the first one that fails. The
+    /// database should be empty, such as an in-memory SQLite database, so that this only checks
+    /// that the migrations run.
+    pub fn validate(&mut self) -> Result<()> {
+        let local_migrations = self.load_local_migrations()?;
+        let repeatables = self.load_repeatable_migrations()?;
+        let db_migrations = Vec::new();
+        let mut plan = PlanBuilder::new()
+            .local_migrations(&local_migrations)
+            .db_migrations(&db_migrations)
+            .up()?;
+        plan.extend(repeatables.iter().map(|m| (Step::Repeat, m)));
+        self.adaptor.run_migration_plan(&plan, &self.run_options)?;
+        display::print_validated(plan.len());
+        Ok(())
+    }
+
+    /// Checks that the `down` of each of the migrations `names` (all of them if empty) undoes
+    /// its `up`. Every local migration is applied in turn on `scratch`, which should be an empty
+    /// database; the checked ones are then rolled back, the schema compared with the one from
+    /// before their `up`, and applied again. Fails with `Error::NotReversible` if any of them
+    /// doesn't restore the schema.
+    pub fn test_reversible<S: DbAdaptor>(
+        &mut self,
+        mut scratch: S,
+        names: &[String],
+    ) -> Result<()> {
+        let mut local_migrations = self.load_local_migrations()?;
+        local_migrations.sort_by(|a, b| migration::compare_names(&a.name, &b.name));
+        if let Some(name) = names
+            .iter()
+            .find(|&n| !local_migrations.iter().any(|m| &m.name == n))
+        {
+            return Err(Error::MigrationNotFound(name.to_owned()));
+        }
+
+        let options = RunOptions {
+            quiet: true,
+            ..self.run_options.clone()
+        };
+        let mut failed = Vec::new();
+        for migration in &local_migrations {
+            let checked = !migration.name.ends_with("_movine_init")
+                && (names.is_empty() || names.contains(&migration.name));
+            let before = if checked {
+                Some(scratch.dump_schema()?)
+            } else {
+                None
+            };
+            scratch.run_migration_plan(&[(Step::Up, migration)], &options)?;
+            let before = match before {
+                Some(before) => before,
+                None => continue,
+            };
+            if !migration.is_reversable() {
+                display::print_round_trip(&migration.name, &RoundTrip::NoDown);
+                continue;
+            }
+
+            let after = scratch.dump_schema()?;
+            let round_trip = match scratch.run_migration_plan(&[(Step::Down, migration)], &options)
+            {
+                Err(e) => RoundTrip::DownFailed(e),
+                Ok(()) => {
+                    let differences = schema::diff(&before, &scratch.dump_schema()?);
+                    let reapplied = scratch.run_migration_plan(&[(Step::Up, migration)], &options);
+                    if let Err(error) = reapplied {
+                        // Whatever the down left behind stops the up from running again, so the
+                        // scratch database can't be trusted for the remaining migrations.
+                        display::print_round_trip(
+                            &migration.name,
+                            &RoundTrip::NotRestored(differences),
+                        );
+                        failed.push(migration.name.to_owned());
+                        display::print_round_trip_aborted(&migration.name, &error);
+                        break;
+                    }
+                    let reapplied = schema::diff(&after, &scratch.dump_schema()?);
+                    if !differences.is_empty() {
+                        RoundTrip::NotRestored(differences)
+                    } else if !reapplied.is_empty() {
+                        RoundTrip::NotReapplied(reapplied)
+                    } else {
+                        RoundTrip::Reversible
+                    }
+                }
+            };
+            display::print_round_trip(&migration.name, &round_trip);
+            if !matches!(round_trip, RoundTrip::Reversible) {
+                failed.push(migration.name.to_owned());
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::NotReversible(failed))
+        }
+    }
+
+    /// Compares the database schema with `expected` and prints the differences. Fails with
+    /// `Error::SchemaDrift` if there are any.
+    pub fn drift(&mut self, expected: &Schema) -> Result<()> {
+        let actual = self.adaptor.dump_schema()?;
+        let differences = schema::diff(expected, &actual);
+        display::print_drift(&differences);
+        if differences.is_empty() {
             Ok(())
         } else {
-            self.adaptor.run_migration_plan(&plan)
+            Err(Error::SchemaDrift(differences.len()))
         }
     }
 
     pub fn down(&mut self) -> Result<()> {
-        let file_handler = FileHandler::new(&self.migration_dir);
-        let local_migrations = file_handler.load_local_migrations()?;
-        let db_migrations = self.adaptor.load_migrations()?;
+        let (local_migrations, db_migrations) = self.load_migrations()?;
 
         let plan = PlanBuilder::new()
             .local_migrations(&local_migrations)
@@ -195,14 +795,37 @@ impl<T: DbAdaptor> Movine<T> {
             display::print_plan(&plan);
             Ok(())
         } else {
-            self.adaptor.run_migration_plan(&plan)
+            let shown = self.confirm_plan(&plan)?;
+            self.adaptor
+                .run_migration_plan(&plan, &self.plan_options(shown)?)
+        }
+    }
+
+    /// Brings the database to migration `target`, running the pending migrations up to it or
+    /// rolling back the ones applied after it.
+    pub fn migrate(&mut self, target: &str) -> Result<()> {
+        let (local_migrations, db_migrations) = self.load_migrations()?;
+        self.check_policy(&local_migrations, &db_migrations)?;
+
+        let plan = PlanBuilder::new()
+            .local_migrations(&local_migrations)
+            .db_migrations(&db_migrations)
+            .set_ignore_divergent(self.ignore_divergent)
+            .set_ignore_unreversable(self.ignore_unreversable)
+            .migrate_to(target)?;
+
+        if self.show_plan {
+            display::print_plan(&plan);
+            Ok(())
+        } else {
+            let shown = self.confirm_plan(&plan)?;
+            self.adaptor
+                .run_migration_plan(&plan, &self.plan_options(shown)?)
         }
     }
 
     pub fn fix(&mut self) -> Result<()> {
-        let file_handler = FileHandler::new(&self.migration_dir);
-        let local_migrations = file_handler.load_local_migrations()?;
-        let db_migrations = self.adaptor.load_migrations()?;
+        let (local_migrations, db_migrations) = self.load_migrations()?;
 
         let plan = PlanBuilder::new()
             .local_migrations(&local_migrations)
@@ -213,14 +836,14 @@ impl<T: DbAdaptor> Movine<T> {
             display::print_plan(&plan);
             Ok(())
         } else {
-            self.adaptor.run_migration_plan(&plan)
+            let shown = self.confirm_plan(&plan)?;
+            self.adaptor
+                .run_migration_plan(&plan, &self.plan_options(shown)?)
         }
     }
 
     pub fn redo(&mut self) -> Result<()> {
-        let file_handler = FileHandler::new(&self.migration_dir);
-        let local_migrations = file_handler.load_local_migrations()?;
-        let db_migrations = self.adaptor.load_migrations()?;
+        let (local_migrations, db_migrations) = self.load_migrations()?;
 
         let plan = PlanBuilder::new()
             .local_migrations(&local_migrations)
@@ -234,7 +857,752 @@ impl<T: DbAdaptor> Movine<T> {
             display::print_plan(&plan);
             Ok(())
         } else {
-            self.adaptor.run_migration_plan(&plan)
+            let shown = self.confirm_plan(&plan)?;
+            self.adaptor
+                .run_migration_plan(&plan, &self.plan_options(shown)?)
+        }
+    }
+
+    /// Runs the seeds in `<seeds_dir>/<env>` in order of their names. `env` defaults to the
+    /// environment set for the database, or else `dev`. With `once`, seeds that were run before
+    /// are skipped by name, so a seed edited since it ran is not run again. Fails with
+    /// `Error::ProtectedEnvironment` if either environment is protected.
+    pub fn seed(&mut self, env: Option<&str>, once: bool) -> Result<()> {
+        let env = env
+            .or(self.environment.as_deref())
+            .unwrap_or(DEFAULT_SEED_ENVIRONMENT)
+            .to_owned();
+        let environments = std::iter::once(env.as_str()).chain(self.environment.as_deref());
+        for environment in environments {
+            if self.is_protected(environment) {
+                return Err(Error::ProtectedEnvironment(environment.to_owned()));
+            }
+        }
+
+        let mut seeds = file_handler::load_seeds(Path::new(&self.seeds_dir), &env)?;
+        self.apply_default_timeouts(&mut seeds);
+        let db_seeds = if once {
+            self.adaptor.load_seeds()?
+        } else {
+            Vec::new()
+        };
+        let plan: Vec<_> = seeds
+            .iter()
+            .filter(|seed| !db_seeds.iter().any(|d| d.name == seed.name))
+            .map(|seed| (Step::Seed, seed))
+            .collect();
+
+        if self.show_plan {
+            display::print_plan(&plan);
+            Ok(())
+        } else {
+            self.adaptor.run_migration_plan(&plan, &self.run_options)
+        }
+    }
+
+    fn is_protected(&self, environment: &str) -> bool {
+        self.protected_environments.iter().any(|p| p == environment)
+    }
+
+    /// Shows the plan and asks whether to run it if it has any `Step::Down` or the database's
+    /// environment is protected, unless confirmation is turned off. Returns whether the plan was
+    /// shown, so that it isn't printed again step by step while running.
+    fn confirm_plan(&self, plan: &[(Step, &Migration)]) -> Result<bool> {
+        let destructive = plan.iter().any(|(step, _)| *step == Step::Down);
+        let protected = matches!(&self.environment, Some(env) if self.is_protected(env));
+        if !self.confirm || plan.is_empty() || !(destructive || protected) {
+            return Ok(false);
+        }
+
+        display::print_plan(plan);
+        if io::stdin().is_terminal() && display::ask_confirmation()? {
+            Ok(true)
+        } else {
+            Err(Error::PlanNotConfirmed)
+        }
+    }
+
+    /// Loads the local and database migrations, with the default timeouts applied.
+    fn load_migrations(&mut self) -> Result<(Vec<Migration>, Vec<Migration>)> {
+        let local_migrations = self.load_local_migrations()?;
+        let mut db_migrations = self.adaptor.load_migrations()?;
+        self.apply_default_timeouts(&mut db_migrations);
+        Ok((local_migrations, db_migrations))
+    }
+
+    /// Options for running a plan against the database, including its hooks. A plan that was
+    /// already `shown` runs quietly.
+    fn plan_options(&self, shown: bool) -> Result<RunOptions> {
+        Ok(RunOptions {
+            hooks: Hooks::load(Path::new(&self.hooks_dir), &self.hook_commands)?,
+            quiet: self.run_options.quiet || shown,
+            ..self.run_options.clone()
+        })
+    }
+
+    fn check_policy(
+        &self,
+        local_migrations: &[Migration],
+        db_migrations: &[Migration],
+    ) -> Result<()> {
+        let violations = self.policy.check(local_migrations, db_migrations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::PolicyViolations(violations))
+        }
+    }
+
+    fn find_local_migration(&self, name: &str) -> Result<Migration> {
+        self.load_local_migrations()?
+            .into_iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| Error::MigrationNotFound(name.to_owned()))
+    }
+
+    fn load_repeatable_migrations(&self) -> Result<Vec<Migration>> {
+        let file_handler = FileHandler::new(&self.migration_dir);
+        let mut repeatables = file_handler.load_repeatable_migrations()?;
+        self.apply_default_timeouts(&mut repeatables);
+        Ok(repeatables)
+    }
+
+    fn load_local_migrations(&self) -> Result<Vec<Migration>> {
+        let file_handler = FileHandler::new(&self.migration_dir);
+        let mut local_migrations = file_handler.load_local_migrations()?;
+        migration::check_naming_schemes(
+            local_migrations.iter().map(|m| m.name.as_str()),
+            self.naming,
+        )?;
+        self.apply_default_timeouts(&mut local_migrations);
+        Ok(local_migrations)
+    }
+
+    fn apply_default_timeouts(&self, migrations: &mut [Migration]) {
+        for migration in migrations {
+            migration.set_default_timeouts(self.lock_timeout, self.statement_timeout);
+        }
+    }
+}
+
+/// The outcome of rolling a migration back and applying it again in `test_reversible`.
+enum RoundTrip {
+    Reversible,
+    /// The migration has no `down`, so there was nothing to check.
+    NoDown,
+    DownFailed(Error),
+    /// The schema after `down` differs from the one before `up`.
+    NotRestored(Vec<schema::Difference>),
+    /// The schema after applying `up` again differs from the one after the first `up`.
+    NotReapplied(Vec<schema::Difference>),
+}
+
+/// The local repeatable migrations that were never applied or have changed since.
+fn outdated_repeatables<'a>(
+    repeatables: &'a [Migration],
+    db_repeatables: &[Migration],
+) -> Vec<&'a Migration> {
+    repeatables
+        .iter()
+        .filter(|m| {
+            !db_repeatables
+                .iter()
+                .any(|d| d.name == m.name && d.hash == m.hash)
+        })
+        .collect()
+}
+
+/// The migrations that squashing up to `up_to` replaces, in order. The init migration is never
+/// squashed, since it creates Movine's own table.
+fn squashable<'a>(migrations: &'a [Migration], up_to: &str) -> Vec<&'a Migration> {
+    let mut squashed: Vec<_> = migrations
+        .iter()
+        .filter(|m| !m.name.ends_with("_movine_init"))
+        .filter(|m| migration::compare_names(&m.name, up_to) != Ordering::Greater)
+        .collect();
+    squashed.sort_by(|a, b| migration::compare_names(&a.name, &b.name));
+    squashed
+}
+
+fn build_baseline(
+    name: &str,
+    local_migrations: &[Migration],
+    squashed: &[&Migration],
+    scratch: Option<&mut dyn DbAdaptor>,
+) -> Result<Migration> {
+    let header = format!(
+        "-- Baseline of the migrations up to and including {}.\n",
+        squashed.last().map_or("", |m| m.name.as_str())
+    );
+    let up_sql = match scratch {
+        Some(scratch) => {
+            let init = local_migrations
+                .iter()
+                .filter(|m| m.name.ends_with("_movine_init"));
+            let plan: Vec<_> = init
+                .chain(squashed.iter().copied())
+                .map(|m| (Step::Up, m))
+                .collect();
+            let options = RunOptions {
+                quiet: true,
+                ..RunOptions::default()
+            };
+            scratch.run_migration_plan(&plan, &options)?;
+            let schema = scratch.dump_schema()?;
+            let definitions: Vec<_> = schema
+                .creation_order()
+                .iter()
+                .map(|o| o.definition.as_str())
+                .collect();
+            format!("{}\n{}\n", header, definitions.join("\n\n"))
+        }
+        None => {
+            let sections: Vec<_> = squashed
+                .iter()
+                .map(|m| {
+                    format!(
+                        "-- {}\n{}",
+                        m.name,
+                        m.up_sql.as_deref().unwrap_or("").trim()
+                    )
+                })
+                .collect();
+            format!("{}\n{}\n", header, sections.join("\n\n"))
+        }
+    };
+    // The baseline can only be rolled back if everything it replaces could be.
+    let down_sql = if squashed.iter().all(|m| m.is_reversable()) {
+        let sections: Vec<_> = squashed
+            .iter()
+            .rev()
+            .map(|m| {
+                format!(
+                    "-- {}\n{}",
+                    m.name,
+                    m.down_sql.as_deref().unwrap_or("").trim()
+                )
+            })
+            .collect();
+        format!("{}\n", sections.join("\n\n"))
+    } else {
+        String::new()
+    };
+
+    MigrationBuilder::new()
+        .compound_name(name)
+        .up_sql(&up_sql)
+        .down_sql(&down_sql)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+    use tempfile::TempDir;
+
+    const USERS: &str = "2020-01-01-000000_users";
+    const POSTS: &str = "2020-01-02-000000_posts";
+    const TAGS: &str = "2020-01-03-000000_tags";
+
+    /// A migration directory with the `(name, up_sql, down_sql)` migrations in the directory
+    /// layout.
+    fn migration_dir(migrations: &[(&str, &str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, up_sql, down_sql) in migrations {
+            let path = dir.path().join(name);
+            fs::create_dir(&path).unwrap();
+            fs::write(path.join("up.sql"), up_sql).unwrap();
+            fs::write(path.join("down.sql"), down_sql).unwrap();
+        }
+        dir
+    }
+
+    fn blog_dir() -> TempDir {
+        migration_dir(&[
+            (
+                USERS,
+                "CREATE TABLE users (id int);\n",
+                "DROP TABLE users;\n",
+            ),
+            (
+                POSTS,
+                "CREATE TABLE posts (id int);\n",
+                "DROP TABLE posts;\n",
+            ),
+            (TAGS, "CREATE TABLE tags (id int);\n", "DROP TABLE tags;\n"),
+        ])
+    }
+
+    /// A `Movine` for the migrations in `dir`, on an initialized in-memory SQLite database.
+    fn sqlite_movine(dir: &TempDir) -> Movine<Connection> {
+        let mut movine = Movine::new(Connection::open_in_memory().unwrap());
+        movine.set_migration_dir(dir.path().to_str().unwrap());
+        movine.initialize().unwrap();
+        movine
+    }
+
+    fn db_names(movine: &mut Movine<Connection>) -> Vec<String> {
+        let mut names: Vec<_> = movine
+            .adaptor
+            .load_migrations()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn local_names(movine: &Movine<Connection>) -> Vec<String> {
+        let mut names: Vec<_> = movine
+            .load_local_migrations()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    /// Everything up to and including `up_to` should be squashed, except the init migration.
+    fn test_squashable() {
+        let dir = blog_dir();
+        let movine = sqlite_movine(&dir);
+        let local_migrations = movine.load_local_migrations().unwrap();
+        let squashed: Vec<_> = squashable(&local_migrations, POSTS)
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect();
+        assert_eq!(squashed, vec![USERS, POSTS]);
+    }
+
+    #[test]
+    /// The baseline should run the squashed up migrations in order and their down migrations
+    /// in reverse, and only be reversible if all of them are.
+    fn test_build_baseline() {
+        let dir = blog_dir();
+        let movine = sqlite_movine(&dir);
+        let local_migrations = movine.load_local_migrations().unwrap();
+        let squashed = squashable(&local_migrations, POSTS);
+        let baseline = build_baseline(
+            "2020-01-02-000000_baseline",
+            &local_migrations,
+            &squashed,
+            None,
+        )
+        .unwrap();
+        assert_eq!(baseline.name, "2020-01-02-000000_baseline");
+        let up_sql = baseline.up_sql.as_deref().unwrap();
+        assert!(
+            up_sql.find("CREATE TABLE users").unwrap() < up_sql.find("CREATE TABLE posts").unwrap()
+        );
+        let down_sql = baseline.down_sql.as_deref().unwrap();
+        assert!(
+            down_sql.find("DROP TABLE posts").unwrap() < down_sql.find("DROP TABLE users").unwrap()
+        );
+
+        let mut local_migrations = local_migrations;
+        let users = local_migrations.iter_mut().find(|m| m.name == USERS);
+        users.unwrap().down_sql = None;
+        let squashed = squashable(&local_migrations, POSTS);
+        let baseline = build_baseline(
+            "2020-01-02-000000_baseline",
+            &local_migrations,
+            &squashed,
+            None,
+        )
+        .unwrap();
+        assert!(!baseline.is_reversable());
+    }
+
+    #[test]
+    /// Squashing applied migrations should archive their files and replace their rows with the
+    /// baseline's, which then counts as applied.
+    fn test_squash() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.up().unwrap();
+        movine.squash(POSTS, None).unwrap();
+
+        let baseline = "2020-01-02-000000_baseline";
+        let expected = vec!["1970-01-01-000000_movine_init", baseline, TAGS];
+        assert_eq!(local_names(&movine), expected);
+        assert_eq!(db_names(&mut movine), expected);
+        assert!(dir.path().join(".archive").join(USERS).exists());
+        assert_eq!(
+            movine
+                .adaptor
+                .load_migrations()
+                .unwrap()
+                .iter()
+                .find(|m| m.name == baseline)
+                .unwrap()
+                .hash,
+            movine.find_local_migration(baseline).unwrap().hash
+        );
+    }
+
+    #[test]
+    /// A database with only some of the squashed migrations applied should be refused before
+    /// any file is changed, and one with none of them left alone.
+    fn test_squash_partially_applied() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.set_number(Some(1)).up().unwrap();
+        let before = local_names(&movine);
+        assert!(matches!(
+            movine.squash(POSTS, None),
+            Err(Error::SquashPartiallyApplied(_))
+        ));
+        assert_eq!(local_names(&movine), before);
+        assert!(!dir.path().join(".archive").exists());
+
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.squash(POSTS, None).unwrap();
+        assert_eq!(db_names(&mut movine), vec!["1970-01-01-000000_movine_init"]);
+        assert!(!local_names(&movine).contains(&USERS.to_owned()));
+    }
+
+    #[test]
+    /// Renaming an applied migration should rename both its files and its row.
+    fn test_rename() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.up().unwrap();
+        let renamed = "2020-01-02-000000_articles";
+        movine.rename(POSTS, renamed).unwrap();
+        assert!(local_names(&movine).contains(&renamed.to_owned()));
+        assert!(db_names(&mut movine).contains(&renamed.to_owned()));
+        assert!(!db_names(&mut movine).contains(&POSTS.to_owned()));
+
+        // Renamed by hand: only the database is updated.
+        fs::rename(dir.path().join(renamed), dir.path().join(POSTS)).unwrap();
+        movine.rename(renamed, POSTS).unwrap();
+        assert_eq!(db_names(&mut movine), local_names(&movine));
+    }
+
+    #[test]
+    /// Invalid, taken or unknown names should be refused without changing anything.
+    fn test_rename_refused() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.up().unwrap();
+        let before = local_names(&movine);
+        for new in &["../2020-01-02-000000_posts", "2020-01-02-000000", "posts"] {
+            assert!(matches!(
+                movine.rename(POSTS, new),
+                Err(Error::InvalidMigrationName { .. })
+            ));
         }
+        assert!(matches!(
+            movine.rename(POSTS, "0002_posts"),
+            Err(Error::MixedNamingSchemes(_))
+        ));
+        assert!(matches!(
+            movine.rename(POSTS, TAGS),
+            Err(Error::MigrationExists(_))
+        ));
+        assert!(matches!(
+            movine.rename("2020-01-09-000000_nope", "2020-01-09-000000_none"),
+            Err(Error::MigrationNotFound(_))
+        ));
+        assert_eq!(local_names(&movine), before);
+        assert_eq!(db_names(&mut movine), before);
+    }
+
+    #[test]
+    /// Migrations whose down doesn't restore the schema, or fails, should be reported, and the
+    /// others pass.
+    fn test_test_reversible() {
+        let leaky = "2020-01-04-000000_audit";
+        let broken = "2020-01-05-000000_broken";
+        let dir = migration_dir(&[
+            (
+                USERS,
+                "CREATE TABLE users (id int);\n",
+                "DROP TABLE users;\n",
+            ),
+            (
+                leaky,
+                "CREATE TABLE posts (id int);\nCREATE TABLE IF NOT EXISTS audit (id int);\n",
+                "DROP TABLE posts;\n",
+            ),
+            (
+                broken,
+                "CREATE TABLE tags (id int);\n",
+                "DROP TABLE nope;\n",
+            ),
+        ]);
+        let mut movine = sqlite_movine(&dir);
+        let scratch = Connection::open_in_memory().unwrap();
+        match movine.test_reversible(scratch, &[]) {
+            Err(Error::NotReversible(names)) => assert_eq!(names, vec![leaky, broken]),
+            _ => panic!("expected the leaky and broken migrations to fail"),
+        }
+
+        let scratch = Connection::open_in_memory().unwrap();
+        movine
+            .test_reversible(scratch, &[USERS.to_owned()])
+            .unwrap();
+    }
+
+    #[test]
+    /// Every migration should be run on the fresh database, failing on broken SQL.
+    fn test_validate() {
+        let dir = blog_dir();
+        sqlite_movine(&dir);
+        let mut movine = Movine::new(Connection::open_in_memory().unwrap());
+        movine.set_migration_dir(dir.path().to_str().unwrap());
+        movine.validate().unwrap();
+        assert_eq!(db_names(&mut movine), local_names(&movine));
+
+        let broken = dir.path().join("2020-01-04-000000_broken");
+        fs::create_dir(&broken).unwrap();
+        fs::write(broken.join("up.sql"), "CREATE TABLE users (id int);\n").unwrap();
+        let mut movine = Movine::new(Connection::open_in_memory().unwrap());
+        movine.set_migration_dir(dir.path().to_str().unwrap());
+        match movine.validate() {
+            Err(Error::MigrationFailed { migration, .. }) => {
+                assert_eq!(migration, "2020-01-04-000000_broken")
+            }
+            _ => panic!("expected the broken migration to fail"),
+        }
+    }
+
+    #[test]
+    /// Verified migrations should run on both databases when the database hasn't drifted.
+    fn test_up_verified() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        let mut shadow = Connection::open_in_memory().unwrap();
+        movine.set_number(Some(1)).up().unwrap();
+        movine.set_number(None).up_verified(&mut shadow).unwrap();
+        assert_eq!(db_names(&mut movine), local_names(&movine));
+        assert_eq!(
+            shadow.dump_schema().unwrap(),
+            movine.adaptor.dump_schema().unwrap()
+        );
+    }
+
+    #[test]
+    /// Drift and broken pending migrations should be caught before the database is migrated.
+    fn test_up_verified_refused() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        movine.set_number(Some(1)).up().unwrap();
+        movine.set_number(None);
+        let before = db_names(&mut movine);
+
+        movine
+            .adaptor
+            .execute_batch("CREATE TABLE extra (id int);")
+            .unwrap();
+        let mut shadow = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            movine.up_verified(&mut shadow),
+            Err(Error::SchemaDrift(1))
+        ));
+        assert_eq!(db_names(&mut movine), before);
+
+        movine.adaptor.execute_batch("DROP TABLE extra;").unwrap();
+        fs::write(
+            dir.path().join(TAGS).join("up.sql"),
+            "CREATE TABLE users (id int);\n",
+        )
+        .unwrap();
+        let mut shadow = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            movine.up_verified(&mut shadow),
+            Err(Error::MigrationFailed { .. })
+        ));
+        assert_eq!(db_names(&mut movine), before);
+        assert!(movine
+            .adaptor
+            .dump_schema()
+            .unwrap()
+            .objects
+            .iter()
+            .all(|o| o.name != "posts"));
+    }
+
+    /// A repeatable migration named `name` with `sql` as its up migration.
+    fn repeatable(name: &str, sql: &str) -> Migration {
+        MigrationBuilder::new()
+            .compound_name(name)
+            .up_sql(sql)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    /// Repeatables that were never applied or whose hash changed should be re-applied.
+    fn test_outdated_repeatables() {
+        let repeatables = [
+            repeatable("functions", "SELECT 1;"),
+            repeatable("new", "SELECT 2;"),
+            repeatable("views", "SELECT 3;"),
+        ];
+        let db_repeatables = [
+            repeatable("functions", "SELECT 1;"),
+            repeatable("views", "SELECT 0;"),
+        ];
+        let names: Vec<_> = outdated_repeatables(&repeatables, &db_repeatables)
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["new", "views"]);
+    }
+
+    #[test]
+    /// `up` should add the `kind` column and re-apply a repeatable migration once its file
+    /// changes. Versioned migrations of the same name should leave its row alone.
+    fn test_repeatable_migrations() {
+        let shared = "2020-01-04-000000_views";
+        let dir = blog_dir();
+        let repeatable_dir = dir.path().join("repeatable");
+        fs::create_dir(&repeatable_dir).unwrap();
+        let views = repeatable_dir.join(format!("{}.sql", shared));
+        fs::write(
+            &views,
+            "CREATE VIEW IF NOT EXISTS user_ids AS SELECT id FROM users;\n",
+        )
+        .unwrap();
+        let mut movine = sqlite_movine(&dir);
+        let has_kind_column =
+            |conn: &Connection| conn.prepare("SELECT kind FROM movine_migrations").is_ok();
+        assert!(!has_kind_column(&movine.adaptor));
+        movine.up().unwrap();
+        assert!(has_kind_column(&movine.adaptor));
+        let applied = movine.adaptor.load_repeatable_migrations().unwrap();
+        assert_eq!(applied.len(), 1);
+
+        fs::write(
+            &views,
+            "DROP VIEW user_ids;\nCREATE VIEW user_ids AS SELECT id AS user_id FROM users;\n",
+        )
+        .unwrap();
+        movine.up().unwrap();
+        let reapplied = movine.adaptor.load_repeatable_migrations().unwrap();
+        assert_eq!(reapplied.len(), 1);
+        assert_ne!(reapplied[0].hash, applied[0].hash);
+        assert!(reapplied[0]
+            .up_sql
+            .as_deref()
+            .unwrap()
+            .contains("user_id FROM"));
+
+        // A versioned migration sharing the repeatable's name.
+        let versioned = dir.path().join(shared);
+        fs::create_dir(&versioned).unwrap();
+        fs::write(versioned.join("up.sql"), "CREATE TABLE shared (id int);\n").unwrap();
+        fs::write(versioned.join("down.sql"), "DROP TABLE shared;\n").unwrap();
+        movine.up().unwrap();
+        movine.set_number(Some(1)).down().unwrap();
+        assert!(!db_names(&mut movine).contains(&shared.to_owned()));
+        assert_eq!(
+            movine.adaptor.load_repeatable_migrations().unwrap().len(),
+            1
+        );
+    }
+
+    fn seeds_dir(seeds: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("dev")).unwrap();
+        for (name, sql) in seeds {
+            fs::write(dir.path().join("dev").join(name), sql).unwrap();
+        }
+        dir
+    }
+
+    fn seed_count(movine: &Movine<Connection>) -> i64 {
+        movine
+            .adaptor
+            .query_row("SELECT COUNT(*) FROM users", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    /// Seeding should refuse protected environments, whether asked for or set for the
+    /// database, before running anything.
+    fn test_seed_protected_environment() {
+        let dir = blog_dir();
+        let seeds = seeds_dir(&[("01_users.sql", "INSERT INTO users VALUES (1);\n")]);
+        let mut movine = sqlite_movine(&dir);
+        movine.up().unwrap();
+        movine
+            .set_seeds_dir(seeds.path().to_str().unwrap())
+            .set_protected_environments(&["prod".to_owned()]);
+
+        match movine.seed(Some("prod"), false) {
+            Err(Error::ProtectedEnvironment(env)) => assert_eq!(env, "prod"),
+            result => panic!("expected a protected environment error, got {:?}", result),
+        }
+        movine.set_environment(Some("prod"));
+        match movine.seed(Some("dev"), false) {
+            Err(Error::ProtectedEnvironment(env)) => assert_eq!(env, "prod"),
+            result => panic!("expected a protected environment error, got {:?}", result),
+        }
+        assert_eq!(seed_count(&movine), 0);
+    }
+
+    #[test]
+    /// With `once`, seeds that ran before should be skipped by name, even after being edited,
+    /// while new seeds still run. Without it, every seed should run again.
+    fn test_seed_once() {
+        let dir = blog_dir();
+        let seeds = seeds_dir(&[("01_users.sql", "INSERT INTO users VALUES (1);\n")]);
+        let mut movine = sqlite_movine(&dir);
+        movine.up().unwrap();
+        movine.set_seeds_dir(seeds.path().to_str().unwrap());
+
+        movine.seed(None, true).unwrap();
+        assert_eq!(seed_count(&movine), 1);
+        movine.seed(None, true).unwrap();
+        assert_eq!(seed_count(&movine), 1);
+
+        let dev = seeds.path().join("dev");
+        fs::write(dev.join("01_users.sql"), "INSERT INTO users VALUES (2);\n").unwrap();
+        fs::write(dev.join("02_users.sql"), "INSERT INTO users VALUES (3);\n").unwrap();
+        movine.seed(None, true).unwrap();
+        assert_eq!(seed_count(&movine), 2);
+        let names: Vec<_> = movine
+            .adaptor
+            .load_seeds()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, vec!["dev/01_users", "dev/02_users"]);
+
+        movine.seed(None, false).unwrap();
+        assert_eq!(seed_count(&movine), 4);
+    }
+
+    #[test]
+    /// Plans without a `Step::Down` on an unprotected environment shouldn't need confirming,
+    /// and nothing should once confirmation is turned off.
+    fn test_confirm_plan() {
+        let dir = blog_dir();
+        let mut movine = sqlite_movine(&dir);
+        let local_migrations = movine.load_local_migrations().unwrap();
+        let users = local_migrations.iter().find(|m| m.name == USERS).unwrap();
+
+        let up_plan = [(Step::Up, users)];
+        let down_plan = [(Step::Down, users)];
+        movine.set_confirm(true);
+        assert!(!movine.confirm_plan(&up_plan).unwrap());
+        assert!(!movine.confirm_plan(&[]).unwrap());
+
+        movine
+            .set_confirm(false)
+            .set_environment(Some("prod"))
+            .set_protected_environments(&["prod".to_owned()]);
+        assert!(!movine.confirm_plan(&up_plan).unwrap());
+        assert!(!movine.confirm_plan(&down_plan).unwrap());
     }
 }
@@ -0,0 +1,380 @@
+use crate::adaptor::Dialect;
+use crate::errors::{Error, Result};
+use crate::migration::{self, Migration};
+use crate::sql::split_statements;
+use serde::Deserialize;
+
+/// The `[lint]` section of `movine.toml`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct LintConfig {
+    /// Names of the rules `lint` should not check.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+/// A risky operation that `lint` looks for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rule {
+    NotNullWithoutDefault,
+    IndexNotConcurrent,
+    ColumnTypeChange,
+    DropColumn,
+    DropTable,
+    Rename,
+    EmptyDown,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::NotNullWithoutDefault,
+        Rule::IndexNotConcurrent,
+        Rule::ColumnTypeChange,
+        Rule::DropColumn,
+        Rule::DropTable,
+        Rule::Rename,
+        Rule::EmptyDown,
+    ];
+
+    /// The name used for the rule in `[lint] disabled` and `-- +movine lint_ignore`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rule::NotNullWithoutDefault => "not_null_without_default",
+            Rule::IndexNotConcurrent => "index_not_concurrent",
+            Rule::ColumnTypeChange => "column_type_change",
+            Rule::DropColumn => "drop_column",
+            Rule::DropTable => "drop_table",
+            Rule::Rename => "rename",
+            Rule::EmptyDown => "empty_down",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        Rule::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.as_str() == name)
+            .ok_or_else(|| Error::UnknownLintRule(name.to_owned()))
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Rule::NotNullWithoutDefault => {
+                "adds a NOT NULL column without a default, which fails if the table has rows"
+            }
+            Rule::IndexNotConcurrent => {
+                concat!(
+                    "creates an index without CONCURRENTLY, which blocks writes to the table ",
+                    "while it builds; run it CONCURRENTLY outside a migration or accept the lock"
+                )
+            }
+            Rule::ColumnTypeChange => {
+                "changes a column's type, which can rewrite the whole table under an exclusive lock"
+            }
+            Rule::DropColumn => "drops a column that running code may still use",
+            Rule::DropTable => "drops a table that running code may still use",
+            Rule::Rename => "renames something that running code may still use by its old name",
+            Rule::EmptyDown => "has no down migration, or one that doesn't do anything",
+        }
+    }
+}
+
+/// A risky operation found in a migration.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub rule: Rule,
+    /// The offending statement and the line it starts on in `up.sql`, unless the warning is
+    /// about the migration as a whole.
+    pub statement: Option<(usize, String)>,
+}
+
+/// Checks the `up` of `migration` for risky operations, and that it has a `down` that does
+/// something. Rules in `disabled` or named in a `-- +movine lint_ignore <rule>...`
+/// directive of the migration are skipped.
+pub fn lint(migration: &Migration, dialect: Dialect, disabled: &[Rule]) -> Result<Vec<Warning>> {
+    let mut skipped = disabled.to_vec();
+    for sql in migration.up_sql.iter().chain(migration.down_sql.iter()) {
+        for (name, value) in migration::directives(sql) {
+            if name == "lint_ignore" {
+                for rule in value.split(|c: char| c == ',' || c.is_whitespace()) {
+                    if !rule.is_empty() {
+                        skipped.push(Rule::parse(rule)?);
+                    }
+                }
+            }
+        }
+    }
+
+    let statements = split_statements(migration.up_sql.as_deref().unwrap_or(""));
+    let tokenized: Vec<_> = statements.iter().map(|s| tokens(s.sql)).collect();
+    let created_tables: Vec<_> = tokenized.iter().filter_map(|t| created_table(t)).collect();
+    let mut warnings = Vec::new();
+    for (statement, tokens) in statements.iter().zip(&tokenized) {
+        for rule in check_statement(tokens, dialect, &created_tables) {
+            warnings.push(Warning {
+                rule,
+                statement: Some((statement.line, statement.sql.to_owned())),
+            });
+        }
+    }
+    if split_statements(migration.down_sql.as_deref().unwrap_or("")).is_empty() {
+        warnings.push(Warning {
+            rule: Rule::EmptyDown,
+            statement: None,
+        });
+    }
+    warnings.retain(|warning| !skipped.contains(&warning.rule));
+    Ok(warnings)
+}
+
+fn check_statement(tokens: &[String], dialect: Dialect, created_tables: &[&str]) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    match words(tokens, 3).as_slice() {
+        ["ALTER", "TABLE", ..] => {
+            let rest = skip_words(&tokens[2..], &["IF", "EXISTS", "ONLY"]);
+            let rest = rest.get(1..).unwrap_or(&[]);
+            for action in rest.split(|t| t == ",") {
+                if let Some(rule) = check_alter_action(action) {
+                    rules.push(rule);
+                }
+            }
+        }
+        ["DROP", "TABLE", ..] => rules.push(Rule::DropTable),
+        ["CREATE", "INDEX", ..] | ["CREATE", "UNIQUE", "INDEX"] if dialect == Dialect::Postgres => {
+            let position = tokens.iter().position(|t| t == "INDEX").unwrap_or(0);
+            let concurrent = matches!(tokens.get(position + 1), Some(t) if t == "CONCURRENTLY");
+            let table = tokens
+                .iter()
+                .position(|t| t == "ON")
+                .and_then(|on| skip_words(&tokens[on + 1..], &["ONLY"]).first());
+            let new_table =
+                matches!(table, Some(table) if created_tables.contains(&table.as_str()));
+            if !concurrent && !new_table {
+                rules.push(Rule::IndexNotConcurrent);
+            }
+        }
+        _ => {}
+    }
+    rules
+}
+
+/// Checks one of the comma-separated actions of an `ALTER TABLE`.
+fn check_alter_action(action: &[String]) -> Option<Rule> {
+    match words(action, 2).as_slice() {
+        ["ADD", "CONSTRAINT"]
+        | ["ADD", "PRIMARY"]
+        | ["ADD", "UNIQUE"]
+        | ["ADD", "FOREIGN"]
+        | ["ADD", "CHECK"]
+        | ["ADD", "EXCLUDE"] => None,
+        ["ADD", ..] => {
+            let not_null = action.windows(2).any(|w| w[0] == "NOT" && w[1] == "NULL");
+            let filled = action.iter().any(|t| t == "DEFAULT" || t == "GENERATED");
+            if not_null && !filled {
+                Some(Rule::NotNullWithoutDefault)
+            } else {
+                None
+            }
+        }
+        ["DROP", "CONSTRAINT"] => None,
+        ["DROP", ..] => Some(Rule::DropColumn),
+        ["RENAME", ..] => Some(Rule::Rename),
+        ["ALTER", ..] => {
+            // ALTER [COLUMN] name [SET DATA] TYPE ...
+            let column = skip_words(&action[1..], &["COLUMN"]);
+            match words(column.get(1..).unwrap_or(&[]), 3).as_slice() {
+                ["TYPE", ..] | ["SET", "DATA", "TYPE"] => Some(Rule::ColumnTypeChange),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The table a `CREATE TABLE` statement creates.
+fn created_table(tokens: &[String]) -> Option<&str> {
+    let position = tokens.iter().position(|t| t == "TABLE")?;
+    if tokens.first()? != "CREATE" || position > 3 {
+        return None;
+    }
+    skip_words(&tokens[position + 1..], &["IF", "NOT", "EXISTS"])
+        .first()
+        .map(|t| t.as_str())
+}
+
+fn words(tokens: &[String], count: usize) -> Vec<&str> {
+    tokens.iter().take(count).map(|t| t.as_str()).collect()
+}
+
+fn skip_words<'a>(tokens: &'a [String], skipped: &[&str]) -> &'a [String] {
+    let start = tokens
+        .iter()
+        .position(|t| !skipped.contains(&t.as_str()))
+        .unwrap_or(tokens.len());
+    &tokens[start..]
+}
+
+/// Splits a statement into upper-cased words and punctuation, dropping comments. Quoted
+/// identifiers become words without their quotes, and string literals a single `'`. Only
+/// top-level commas are kept, so they separate the actions of an `ALTER TABLE`.
+fn tokens(sql: &str) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut parens = 0usize;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    i += 1;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    i += 1;
+                    if depth == 0 {
+                        i += 1;
+                        break;
+                    }
+                }
+                i += 1;
+            }
+        } else if c == '\'' || c == '"' || c == '`' {
+            // A doubled quote is an escaped quote, as is `\'` in a Postgres `E'...'` string.
+            let backslash_escapes = c == '\'' && tokens.last().is_some_and(|t| t == "E");
+            let mut end = i + 1;
+            while end < chars.len() {
+                if (backslash_escapes && chars[end] == '\\')
+                    || (chars[end] == c && chars.get(end + 1) == Some(&c))
+                {
+                    end += 2;
+                } else if chars[end] == c {
+                    break;
+                } else {
+                    end += 1;
+                }
+            }
+            let end = end.min(chars.len());
+            if c == '\'' {
+                if backslash_escapes {
+                    tokens.pop();
+                }
+                tokens.push("'".to_owned());
+            } else {
+                tokens.push(chars[i + 1..end].iter().collect::<String>().to_uppercase());
+            }
+            i = end + 1;
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '$' | '.'))
+            {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect::<String>().to_uppercase());
+        } else {
+            match c {
+                '(' => parens += 1,
+                ')' => parens = parens.saturating_sub(1),
+                ',' if parens == 0 => tokens.push(",".to_owned()),
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::MigrationBuilder;
+
+    /// A down migration that `empty_down` is happy with.
+    const DOWN: &str = "SELECT 1;";
+
+    fn rules(up_sql: &str, down_sql: Option<&str>, dialect: Dialect) -> Vec<&'static str> {
+        let mut builder = MigrationBuilder::new();
+        builder.compound_name("test").up_sql(up_sql);
+        if let Some(down_sql) = down_sql {
+            builder.down_sql(down_sql);
+        }
+        lint(&builder.build().unwrap(), dialect, &[])
+            .unwrap()
+            .iter()
+            .map(|w| w.rule.as_str())
+            .collect()
+    }
+
+    #[test]
+    /// Each rule should flag its operation, and leave the safe variants alone.
+    fn test_lint_rules() {
+        let sql = "ALTER TABLE a ADD COLUMN b int NOT NULL, ADD COLUMN c int NOT NULL DEFAULT 0;
+            ALTER TABLE ONLY a ADD CONSTRAINT u UNIQUE (b), DROP CONSTRAINT v, DROP COLUMN d;
+            ALTER TABLE a ALTER COLUMN e TYPE bigint, ALTER type SET NOT NULL;
+            ALTER TABLE a RENAME TO f;
+            DROP TABLE g;
+            CREATE INDEX h ON a (b);
+            CREATE INDEX CONCURRENTLY i ON a (b);
+            CREATE TABLE j (k int NOT NULL);
+            CREATE UNIQUE INDEX l ON j (k);";
+        assert_eq!(
+            rules(sql, Some("-- nothing to undo\n"), Dialect::Postgres),
+            vec![
+                "not_null_without_default",
+                "drop_column",
+                "column_type_change",
+                "rename",
+                "drop_table",
+                "index_not_concurrent",
+                "empty_down",
+            ]
+        );
+        assert_eq!(
+            rules("CREATE INDEX h ON a (b);", Some(DOWN), Dialect::Sqlite),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            rules("CREATE TABLE a (b int);", None, Dialect::Postgres),
+            vec!["empty_down"]
+        );
+    }
+
+    #[test]
+    /// Quotes escaped inside string literals and identifiers shouldn't end them early.
+    fn test_tokens_escaped_quotes() {
+        assert_eq!(
+            tokens("ALTER TABLE a ADD COLUMN b text DEFAULT 'it''s', \"c\"\"d\""),
+            vec![
+                "ALTER", "TABLE", "A", "ADD", "COLUMN", "B", "TEXT", "DEFAULT", "'", ",", "C\"\"D"
+            ]
+        );
+        let sql = "ALTER TABLE a ALTER COLUMN b SET DEFAULT E'it\\'s, DROP COLUMN c';";
+        assert_eq!(
+            rules(sql, Some(DOWN), Dialect::Postgres),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    /// A `lint_ignore` directive should skip the rules it names, and reject unknown ones.
+    fn test_lint_ignore() {
+        let sql =
+            "-- +movine lint_ignore drop_table, rename\nDROP TABLE a;\nALTER TABLE b RENAME TO c;";
+        assert_eq!(
+            rules(sql, Some(DOWN), Dialect::Postgres),
+            Vec::<&str>::new()
+        );
+
+        let migration = MigrationBuilder::new()
+            .compound_name("test")
+            .up_sql("-- +movine lint_ignore drop_everything\n")
+            .build()
+            .unwrap();
+        assert!(lint(&migration, Dialect::Postgres, &[]).is_err());
+    }
+}
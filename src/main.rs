@@ -1,78 +1,257 @@
 use movine::config::Config;
 use movine::errors::Result;
+use movine::schema::Schema;
+use movine::scratch::ScratchDatabase;
 use movine::DbAdaptor;
-use movine::Movine;
+use movine::{Layout, Movine};
+use std::fs;
+use std::process;
 use structopt::StructOpt;
 
 mod cli;
-use cli::Opt;
+use cli::{ConnectionOpts, Opt};
 
-fn main() -> Result<()> {
-    match Opt::from_args() {
-        Opt::Init { debug } => {
-            let mut movine = setup(debug)?;
+fn main() {
+    if let Err(error) = run(Opt::from_args()) {
+        eprintln!("Error: {}", error);
+        process::exit(error.exit_code());
+    }
+}
+
+fn run(opt: Opt) -> Result<()> {
+    match opt {
+        Opt::Init { debug, connection } => {
+            let mut movine = setup(debug, &connection)?;
             movine.initialize()
         }
-        Opt::Generate { name, debug } => {
-            let mut movine = setup(debug)?;
+        Opt::Generate {
+            name,
+            template,
+            single_file,
+            description,
+            debug,
+        } => {
+            let mut movine = offline(debug)?;
+            movine.set_description(description.as_deref());
+            if template.is_some() {
+                movine.set_template(template.as_deref());
+            }
+            if single_file {
+                movine.set_layout(Layout::SingleFile);
+            }
             movine.generate(&name)
         }
-        Opt::Status { debug } => {
-            let mut movine = setup(debug)?;
+        Opt::Status { debug, connection } => {
+            let mut movine = setup(debug, &connection)?;
             movine.status()
         }
         Opt::Up {
             number,
             show_plan,
+            yes,
             debug,
+            connection,
             strict,
+            dump_schema,
+            verify_shadow,
         } => {
-            let mut movine = setup(debug)?;
+            let config = load_config(debug, &connection)?;
+            let mut movine = new_movine(&config)?;
             movine
                 .set_number(number)
                 .set_strict(strict)
                 .set_show_plan(show_plan)
-                .up()
+                .set_confirm(!yes)
+                .set_dump_schema(dump_schema.as_deref());
+            if verify_shadow && !show_plan {
+                let mut shadow = ScratchDatabase::shadow(&config)?;
+                movine.up_verified(shadow.adaptor())
+            } else {
+                movine.up()
+            }
         }
         Opt::Down {
             number,
             show_plan,
             ignore_divergent,
             ignore_unreversable,
+            yes,
             debug,
+            connection,
         } => {
-            let mut movine = setup(debug)?;
+            let mut movine = setup(debug, &connection)?;
             movine
                 .set_number(number)
                 .set_show_plan(show_plan)
+                .set_confirm(!yes)
                 .set_ignore_divergent(ignore_divergent)
                 .set_ignore_unreversable(ignore_unreversable)
                 .down()
         }
+        Opt::Migrate {
+            to,
+            show_plan,
+            ignore_divergent,
+            ignore_unreversable,
+            yes,
+            debug,
+            connection,
+        } => {
+            let mut movine = setup(debug, &connection)?;
+            movine
+                .set_show_plan(show_plan)
+                .set_confirm(!yes)
+                .set_ignore_divergent(ignore_divergent)
+                .set_ignore_unreversable(ignore_unreversable)
+                .migrate(&to)
+        }
         Opt::Redo {
             number,
             show_plan,
             ignore_divergent,
             ignore_unreversable,
+            yes,
             debug,
+            connection,
         } => {
-            let mut movine = setup(debug)?;
+            let mut movine = setup(debug, &connection)?;
             movine
                 .set_number(number)
                 .set_ignore_divergent(ignore_divergent)
                 .set_ignore_unreversable(ignore_unreversable)
                 .set_show_plan(show_plan)
+                .set_confirm(!yes)
                 .redo()
         }
-        Opt::Fix { show_plan, debug } => {
-            let mut movine = setup(debug)?;
-            movine.set_show_plan(show_plan).fix()
+        Opt::Fix {
+            show_plan,
+            yes,
+            debug,
+            connection,
+        } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.set_show_plan(show_plan).set_confirm(!yes).fix()
+        }
+        Opt::DumpSchema {
+            output,
+            debug,
+            connection,
+        } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.dump_schema(output.as_deref())
+        }
+        Opt::Drift {
+            snapshot,
+            from_migrations,
+            debug,
+            connection,
+        } => {
+            let config = load_config(debug, &connection)?;
+            let mut movine = new_movine(&config)?;
+            let expected = if from_migrations {
+                let mut scratch = ScratchDatabase::create(&config)?;
+                movine.local_schema(scratch.adaptor())?
+            } else {
+                Schema::parse(&fs::read_to_string(&snapshot)?)?
+            };
+            movine.drift(&expected)
+        }
+        Opt::Squash {
+            up_to,
+            from_schema,
+            debug,
+            connection,
+        } => {
+            let config = load_config(debug, &connection)?;
+            let mut movine = new_movine(&config)?;
+            if from_schema {
+                let mut scratch = ScratchDatabase::create(&config)?;
+                movine.squash(&up_to, Some(scratch.adaptor()))
+            } else {
+                movine.squash(&up_to, None)
+            }
+        }
+        Opt::Rename {
+            old,
+            new,
+            debug,
+            connection,
+        } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.rename(&old, &new)
+        }
+        Opt::TestReversible {
+            names,
+            debug,
+            connection,
+        } => {
+            let config = load_config(debug, &connection)?;
+            let mut movine = new_movine(&config)?;
+            let mut scratch = ScratchDatabase::create(&config)?;
+            movine.test_reversible(scratch.adaptor(), &names)
+        }
+        Opt::Seed {
+            env,
+            once,
+            show_plan,
+            debug,
+            connection,
+        } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.set_show_plan(show_plan).seed(env.as_deref(), once)
+        }
+        Opt::Check { debug, connection } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.check()
+        }
+        Opt::Lint { debug, connection } => {
+            let mut movine = setup(debug, &connection)?;
+            movine.lint()
+        }
+        Opt::Validate {
+            shadow: true,
+            debug,
+            connection,
+        } => {
+            let config = load_config(debug, &connection)?;
+            let mut movine = new_movine(&config)?;
+            let mut shadow = ScratchDatabase::shadow(&config)?;
+            let expected = movine.local_schema(shadow.adaptor())?;
+            movine.drift(&expected)
+        }
+        Opt::Validate { debug, .. } => {
+            let mut movine = offline(debug)?;
+            movine.validate()
         }
         _ => unimplemented!(),
     }
 }
 
-fn setup(debug: bool) -> Result<Movine<Box<dyn DbAdaptor>>> {
+fn setup(debug: bool, connection: &ConnectionOpts) -> Result<Movine<Box<dyn DbAdaptor>>> {
+    let config = load_config(debug, connection)?;
+    new_movine(&config)
+}
+
+/// Sets up commands that don't use the configured database on an in-memory SQLite database,
+/// with only the settings from the config file, so that they need no connection parameters.
+fn offline(debug: bool) -> Result<Movine<rusqlite::Connection>> {
+    init(debug);
+    let config = Config::load_settings("movine.toml")?;
+    let mut movine = Movine::new(rusqlite::Connection::open_in_memory()?);
+    movine.configure(&config);
+    Ok(movine)
+}
+
+fn load_config(debug: bool, connection: &ConnectionOpts) -> Result<Config> {
+    init(debug);
+    let mut config = Config::load("movine.toml")?;
+    if connection.wait.is_some() {
+        config.connect_timeout = connection.wait;
+    }
+    Ok(config)
+}
+
+fn init(debug: bool) {
     dotenv::dotenv().ok();
     env_logger::builder()
         .filter_level(if debug {
@@ -81,9 +260,11 @@ fn setup(debug: bool) -> Result<Movine<Box<dyn DbAdaptor>>> {
             log::LevelFilter::Info
         })
         .init();
+}
 
-    let config = Config::load("movine.toml")?;
-    let adaptor = config.into_db_adaptor()?;
-    let movine = Movine::new(adaptor);
+fn new_movine(config: &Config) -> Result<Movine<Box<dyn DbAdaptor>>> {
+    let adaptor = config.clone().into_db_adaptor()?;
+    let mut movine = Movine::new(adaptor);
+    movine.configure(config);
     Ok(movine)
 }
@@ -1,4 +1,4 @@
-use crate::migration::Migration;
+use crate::migration::{self, Migration};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
@@ -32,6 +32,35 @@ pub fn find_matches<'a>(
     matches
 }
 
+/// Pairs of divergent and pending migrations that are probably the same migration renamed
+/// locally: they have the same hash, and no other divergent or pending migration does.
+pub fn find_renames<'a>(matchings: &[Matching<'a>]) -> Vec<(&'a str, &'a str)> {
+    let with_hash = |hash: &Option<String>, divergent: bool| {
+        matchings
+            .iter()
+            .filter_map(|m| match m {
+                Matching::Divergent(x) if divergent && &x.hash == hash => Some(x.name.as_str()),
+                Matching::Pending(x) if !divergent && &x.hash == hash => Some(x.name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut renames = Vec::new();
+    for matching in matchings {
+        if let Matching::Divergent(x) = matching {
+            if x.hash.is_none() {
+                continue;
+            }
+            let (old, new) = (with_hash(&x.hash, true), with_hash(&x.hash, false));
+            if old.len() == 1 && new.len() == 1 {
+                renames.push((old[0], new[0]));
+            }
+        }
+    }
+    renames
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Matching<'a> {
     Applied(&'a Migration),
@@ -84,7 +113,7 @@ impl Ord for Matching<'_> {
     fn cmp(&self, other: &Self) -> Ordering {
         let self_name = self.get_name();
         let other_name = other.get_name();
-        self_name.cmp(other_name)
+        migration::compare_names(self_name, other_name)
     }
 }
 
@@ -93,3 +122,40 @@ impl PartialOrd for Matching<'_> {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::MigrationBuilder;
+
+    fn migration(name: &str, up_sql: &str) -> Migration {
+        MigrationBuilder::new()
+            .compound_name(name)
+            .up_sql(up_sql)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    /// A divergent and a pending migration with the same hash should be reported as a rename,
+    /// unless another divergent or pending migration has that hash too.
+    fn test_find_renames() {
+        let local = vec![
+            migration("0001_users", "CREATE TABLE users (id int);"),
+            migration("0002_posts", "CREATE TABLE posts (id int);"),
+        ];
+        let db = vec![
+            migration("0001_users", "CREATE TABLE users (id int);"),
+            migration("0002_post", "CREATE TABLE posts (id int);"),
+        ];
+        let matchings = find_matches(&local, &db);
+        assert_eq!(find_renames(&matchings), vec![("0002_post", "0002_posts")]);
+
+        let local = vec![
+            migration("0002_posts", "CREATE TABLE posts (id int);"),
+            migration("0003_posts", "CREATE TABLE posts (id int);"),
+        ];
+        let matchings = find_matches(&local, &db);
+        assert_eq!(find_renames(&matchings), Vec::new());
+    }
+}
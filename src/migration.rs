@@ -1,7 +1,14 @@
+use crate::config::parse_duration;
 use crate::errors::{Error, Result};
 use chrono::prelude::*;
+use serde::Deserialize;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+const DIRECTIVE_PREFIX: &str = "-- +movine ";
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Migration {
@@ -9,6 +16,8 @@ pub struct Migration {
     pub up_sql: Option<String>,
     pub down_sql: Option<String>,
     pub hash: Option<String>,
+    pub lock_timeout: Option<Duration>,
+    pub statement_timeout: Option<Duration>,
 }
 
 impl Migration {
@@ -20,15 +29,160 @@ impl Migration {
             false
         }
     }
+
+    /// Fills in any timeouts the migration does not set itself.
+    pub fn set_default_timeouts(
+        &mut self,
+        lock_timeout: Option<Duration>,
+        statement_timeout: Option<Duration>,
+    ) {
+        self.lock_timeout = self.lock_timeout.or(lock_timeout);
+        self.statement_timeout = self.statement_timeout.or(statement_timeout);
+    }
+}
+
+/// How the version prefix of a migration name is formed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamingScheme {
+    /// `2019-03-17-163451_name`
+    #[default]
+    Timestamp,
+    /// `0042_name`
+    Sequential,
+    /// `1552840491_name`
+    Unix,
+}
+
+impl NamingScheme {
+    /// Works out which scheme a migration name follows, if any. A 10-digit version fits both
+    /// the sequential and the unix scheme; it is taken to follow `configured` if that is one of
+    /// them, and the unix scheme otherwise.
+    pub fn detect(name: &str, configured: NamingScheme) -> Option<Self> {
+        let version = version(name);
+        let is_timestamp = version.len() == 17
+            && version.char_indices().all(|(i, c)| match i {
+                4 | 7 | 10 => c == '-',
+                _ => c.is_ascii_digit(),
+            });
+        if is_timestamp {
+            Some(NamingScheme::Timestamp)
+        } else if numeric_version(name).is_none() {
+            None
+        } else if version.len() == 10 && configured == NamingScheme::Sequential {
+            Some(NamingScheme::Sequential)
+        } else if version.len() == 10 {
+            Some(NamingScheme::Unix)
+        } else {
+            Some(NamingScheme::Sequential)
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NamingScheme::Timestamp => "timestamp",
+            NamingScheme::Sequential => "sequential",
+            NamingScheme::Unix => "unix",
+        }
+    }
+}
+
+/// The version prefix of a migration name, i.e. everything before the first `_`.
+pub fn version(name: &str) -> &str {
+    name.split('_').next().unwrap_or(name)
+}
+
+/// The version of a sequential or unix-timestamped migration name.
+pub fn numeric_version(name: &str) -> Option<u64> {
+    let version = version(name);
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    version.parse().ok()
+}
+
+/// Orders migration names by version. Numeric versions are compared as numbers so that
+/// sequential names keep their order past the zero-padding, and come before all other names,
+/// which are compared as a whole. Keeping the two apart makes this a total order.
+pub fn compare_names(a: &str, b: &str) -> Ordering {
+    match (numeric_version(a), numeric_version(b)) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Errors unless `name` is a valid migration name: a version in one of the naming schemes
+/// followed by `_` and a description, with nothing that would put its files elsewhere.
+pub fn check_name(name: &str, configured: NamingScheme) -> Result<()> {
+    let description = name
+        .split_once('_')
+        .map_or("", |(_, description)| description);
+    let reason = if name.contains(['/', '\\']) || name.starts_with('.') {
+        "it must not contain path separators or start with a `.`"
+    } else if NamingScheme::detect(name, configured).is_none() {
+        "its version doesn't follow any naming scheme"
+    } else if description.is_empty() {
+        "it has no `_` and description after its version"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidMigrationName {
+        name: name.to_owned(),
+        reason,
+    })
+}
+
+/// Errors if the given migration names follow more than one naming scheme, `configured` being
+/// the one in use.
+pub fn check_naming_schemes<'a, I>(names: I, configured: NamingScheme) -> Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut by_scheme: BTreeMap<NamingScheme, Vec<&str>> = BTreeMap::new();
+    for name in names {
+        if let Some(scheme) = NamingScheme::detect(name, configured) {
+            by_scheme.entry(scheme).or_default().push(name);
+        }
+    }
+
+    if by_scheme.len() > 1 {
+        let found = by_scheme
+            .iter_mut()
+            .map(|(scheme, names)| {
+                names.sort_by(|a, b| compare_names(a, b));
+                format!("{}: {}", scheme.as_str(), names.join(", "))
+            })
+            .collect();
+        return Err(Error::MixedNamingSchemes(found));
+    }
+    Ok(())
+}
+
+/// Iterates over the `-- +movine <name> [value]` directives in a piece of SQL.
+pub fn directives(sql: &str) -> impl Iterator<Item = (&str, &str)> {
+    sql.lines().filter_map(parse_directive)
+}
+
+/// Parses a single `-- +movine <name> [value]` line.
+pub fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let directive = line.trim().strip_prefix(DIRECTIVE_PREFIX)?.trim();
+    let mut parts = directive.splitn(2, char::is_whitespace);
+    let name = parts.next()?;
+    Some((name, parts.next().unwrap_or("").trim()))
 }
 
 pub struct MigrationBuilder {
     compound_name: Option<String>,
     name: Option<String>,
     date: Option<DateTime<Utc>>,
+    naming: NamingScheme,
+    sequence: Option<u64>,
     up_sql: Option<String>,
     down_sql: Option<String>,
     hash: Option<String>,
+    lenient: bool,
 }
 
 impl MigrationBuilder {
@@ -37,9 +191,12 @@ impl MigrationBuilder {
             compound_name: None,
             name: None,
             date: None,
+            naming: NamingScheme::default(),
+            sequence: None,
             up_sql: None,
             down_sql: None,
             hash: None,
+            lenient: false,
         }
     }
 
@@ -58,6 +215,17 @@ impl MigrationBuilder {
         self
     }
 
+    pub fn naming(&mut self, naming: NamingScheme) -> &mut Self {
+        self.naming = naming;
+        self
+    }
+
+    /// The version number used by the sequential naming scheme.
+    pub fn sequence(&mut self, sequence: u64) -> &mut Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
     pub fn up_sql<'a>(&'a mut self, up_sql: &str) -> &'a mut Self {
         self.up_sql = Some(up_sql.to_owned());
         self
@@ -73,20 +241,53 @@ impl MigrationBuilder {
         self
     }
 
+    /// Skips directives that fail to parse, with a warning, rather than failing the build. For
+    /// SQL loaded from the database, which can't be fixed by editing a file.
+    pub fn lenient(&mut self) -> &mut Self {
+        self.lenient = true;
+        self
+    }
+
     pub fn build(&self) -> Result<Migration> {
         // TODO: Clean up ownership a bit; we should be able to just take
         let name = if let Some(compound_name) = &self.compound_name {
             compound_name.to_owned()
         } else {
-            let name = self.name.to_owned().ok_or(Error::BadMigration)?;
-            let date = self.date.to_owned().ok_or(Error::BadMigration)?;
-            let date = date.format("%Y-%m-%d-%H%M%S").to_string();
-            format!("{}_{}", date, name)
+            let name = self
+                .name
+                .to_owned()
+                .ok_or_else(|| Error::BadMigration("no name given".to_owned()))?;
+            let version = match self.naming {
+                NamingScheme::Timestamp => {
+                    let date = self
+                        .date
+                        .to_owned()
+                        .ok_or_else(|| Error::BadMigration("no date given".to_owned()))?;
+                    date.format("%Y-%m-%d-%H%M%S").to_string()
+                }
+                NamingScheme::Unix => {
+                    let date = self
+                        .date
+                        .to_owned()
+                        .ok_or_else(|| Error::BadMigration("no date given".to_owned()))?;
+                    format!("{:010}", date.timestamp())
+                }
+                NamingScheme::Sequential => {
+                    let sequence = self.sequence.ok_or_else(|| {
+                        Error::BadMigration("no sequence number given".to_owned())
+                    })?;
+                    format!("{:04}", sequence)
+                }
+            };
+            format!("{}_{}", version, name)
         };
 
         let hash = match (&self.up_sql, &self.down_sql, &self.hash) {
             (_, _, Some(x)) => Some(x.to_owned()),
             (x, y, None) => {
+                // A missing down migration hashes like an empty one, so that a migration
+                // hashes the same in every layout.
+                let y = Some(y.as_deref().unwrap_or(""));
                 let mut hasher = DefaultHasher::new();
                 x.hash(&mut hasher);
                 y.hash(&mut hasher);
@@ -94,11 +295,182 @@ impl MigrationBuilder {
             }
         };
 
+        // Directives in `up.sql` take precedence over the ones in `down.sql`.
+        let mut lock_timeout = None;
+        let mut statement_timeout = None;
+        for sql in self.down_sql.iter().chain(self.up_sql.iter()) {
+            for (directive, value) in directives(sql) {
+                let timeout = match directive {
+                    "lock_timeout" => &mut lock_timeout,
+                    "statement_timeout" => &mut statement_timeout,
+                    _ => continue,
+                };
+                match parse_duration(value) {
+                    Ok(duration) => *timeout = Some(duration),
+                    Err(e) if self.lenient => {
+                        warn!("Ignoring `{}` in `{}`: {}", directive, name, e)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
         Ok(Migration {
             name,
             up_sql: self.up_sql.to_owned(),
             down_sql: self.down_sql.to_owned(),
             hash,
+            lock_timeout,
+            statement_timeout,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Timeout directives should be read from the SQL, with `up.sql` taking precedence.
+    fn test_timeout_directives() {
+        let migration = MigrationBuilder::new()
+            .compound_name("test")
+            .up_sql("-- +movine lock_timeout 5s\nALTER TABLE t ADD COLUMN x int;\n")
+            .down_sql("-- +movine lock_timeout 1s\n-- +movine statement_timeout 1min\n")
+            .build()
+            .unwrap();
+        assert_eq!(migration.lock_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(migration.statement_timeout, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    /// An invalid directive should fail a local migration, but not one loaded from the database.
+    fn test_lenient_directives() {
+        let down_sql = "-- +movine lock_timeout soon\nDROP TABLE t;\n";
+        let strict = MigrationBuilder::new()
+            .compound_name("test")
+            .down_sql(down_sql)
+            .build();
+        assert!(matches!(strict, Err(Error::InvalidDuration(_))));
+        let lenient = MigrationBuilder::new()
+            .compound_name("test")
+            .down_sql(down_sql)
+            .lenient()
+            .build()
+            .unwrap();
+        assert_eq!(lenient.lock_timeout, None);
+    }
+
+    #[test]
+    /// Each naming scheme should be recognized from the names it generates.
+    fn test_detect_naming_scheme() {
+        let date = Utc.timestamp_opt(1552840491, 0).unwrap();
+        for &scheme in &[
+            NamingScheme::Timestamp,
+            NamingScheme::Sequential,
+            NamingScheme::Unix,
+        ] {
+            let migration = MigrationBuilder::new()
+                .name("add_users")
+                .date(date)
+                .naming(scheme)
+                .sequence(42)
+                .build()
+                .unwrap();
+            assert_eq!(NamingScheme::detect(&migration.name, scheme), Some(scheme));
+        }
+        assert_eq!(
+            NamingScheme::detect("add_users", NamingScheme::Timestamp),
+            None
+        );
+
+        // A 10-digit version is ambiguous, so the configured scheme decides.
+        assert_eq!(
+            NamingScheme::detect("1000000000_a", NamingScheme::Sequential),
+            Some(NamingScheme::Sequential)
+        );
+        assert_eq!(
+            NamingScheme::detect("1000000000_a", NamingScheme::Unix),
+            Some(NamingScheme::Unix)
+        );
+        assert_eq!(
+            NamingScheme::detect("1000000000_a", NamingScheme::Timestamp),
+            Some(NamingScheme::Unix)
+        );
+        assert_eq!(
+            NamingScheme::detect("0042_a", NamingScheme::Unix),
+            Some(NamingScheme::Sequential)
+        );
+    }
+
+    #[test]
+    /// Sequential versions should be ordered numerically, not lexically.
+    fn test_compare_names() {
+        assert_eq!(compare_names("9999_a", "10000_b"), Ordering::Less);
+        assert_eq!(compare_names("0042_a", "0042_b"), Ordering::Less);
+        assert_eq!(
+            compare_names("2019-03-17-163451_a", "1970-01-01-000000_b"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    /// Names mixing numeric and other versions should still sort consistently.
+    fn test_compare_names_total_order() {
+        let names = ["10_a", "5x_b", "9_c", "misc", "0001_d"];
+        for a in &names {
+            for b in &names {
+                assert_eq!(compare_names(a, b), compare_names(b, a).reverse());
+                for c in &names {
+                    if compare_names(a, b) == Ordering::Less
+                        && compare_names(b, c) == Ordering::Less
+                    {
+                        assert_eq!(compare_names(a, c), Ordering::Less, "{} {} {}", a, b, c);
+                    }
+                }
+            }
+        }
+        let mut sorted = names.to_vec();
+        sorted.sort_by(|a, b| compare_names(a, b));
+        assert_eq!(sorted, ["0001_d", "9_c", "10_a", "5x_b", "misc"]);
+    }
+
+    #[test]
+    /// Names should need a version, a description and no path separators.
+    fn test_check_name() {
+        assert!(check_name("0002_add_users", NamingScheme::Sequential).is_ok());
+        assert!(check_name("2019-03-17-163451_users", NamingScheme::Timestamp).is_ok());
+        for name in &[
+            "0002",
+            "0002_",
+            "users",
+            "0002_a/b",
+            "0002_a\\b",
+            "../0002_users",
+        ] {
+            assert!(
+                matches!(
+                    check_name(name, NamingScheme::Sequential),
+                    Err(Error::InvalidMigrationName { .. })
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    /// Mixing naming schemes should be an error, unrecognized names are ignored.
+    fn test_check_naming_schemes() {
+        assert!(check_naming_schemes(
+            vec!["0000_movine_init", "0001_a", "misc"],
+            NamingScheme::Sequential
+        )
+        .is_ok());
+        assert!(check_naming_schemes(
+            vec!["1970-01-01-000000_movine_init", "0001_a"],
+            NamingScheme::Sequential
+        )
+        .is_err());
+    }
+}
@@ -8,6 +8,10 @@ pub type Plan<'a> = Vec<(Step, &'a Migration)>;
 pub enum Step {
     Up,
     Down,
+    /// Apply a repeatable migration again.
+    Repeat,
+    /// Load seed data.
+    Seed,
 }
 
 pub struct PlanBuilder<'a> {
@@ -133,6 +137,40 @@ impl<'a> PlanBuilder<'a> {
         Ok(plan)
     }
 
+    /// Rolls back the migrations applied after `target` (newest first), then runs the pending
+    /// migrations up to and including it. Divergent and irreversible migrations after `target`
+    /// are an error unless ignored, in which case they stay applied.
+    pub fn migrate_to(self, target: &str) -> Result<Plan<'a>> {
+        let matches = self.get_matches()?;
+        let position = matches
+            .iter()
+            .position(|m| m.get_name() == target)
+            .ok_or_else(|| Error::MigrationNotFound(target.to_owned()))?;
+        let (reached, beyond) = matches.split_at(position + 1);
+
+        let mut plan: Plan<'a> = Vec::new();
+        for m in beyond.iter().rev() {
+            match m {
+                Matching::Divergent(_) if self.ignore_divergent => {}
+                Matching::Divergent(_) => return Err(Error::DivergentMigration),
+                Matching::Applied(_) | Matching::Variant(_, _) => {
+                    if m.is_reversable() {
+                        plan.push((Step::Down, m.get_best_down_migration()));
+                    } else if !self.ignore_unreversable {
+                        return Err(Error::UnrollbackableMigration);
+                    }
+                }
+                Matching::Pending(_) => {}
+            }
+        }
+        for m in reached {
+            if let Matching::Pending(x) = m {
+                plan.push((Step::Up, x));
+            }
+        }
+        Ok(plan)
+    }
+
     pub fn fix(self) -> Result<Plan<'a>> {
         let matches = self.get_matches()?;
 
@@ -254,6 +292,8 @@ mod tests {
                 up_sql: None,
                 down_sql: Some("test".to_owned()),
                 hash: None,
+                lock_timeout: None,
+                statement_timeout: None,
             }
         }
 
@@ -263,6 +303,8 @@ mod tests {
                 up_sql: None,
                 down_sql: None,
                 hash: Some(hash.to_string()),
+                lock_timeout: None,
+                statement_timeout: None,
             }
         }
     }
@@ -335,6 +377,81 @@ mod tests {
         assert_eq!(plan, [(Step::Down, &local[0])])
     }
 
+    #[test]
+    /// Migrating to a target should run the pending migrations up to it, or roll back the
+    /// migrations applied after it, newest first.
+    fn test_migrate_to_1() {
+        let local = [
+            Migration::new("test_0"),
+            Migration::new("test_1"),
+            Migration::new("test_2"),
+            Migration::new("test_3"),
+        ];
+        let db = [Migration::new("test_0")];
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .migrate_to("test_2")
+            .unwrap();
+        assert_eq!(plan, [(Step::Up, &local[1]), (Step::Up, &local[2])]);
+
+        let db = [
+            Migration::new("test_0"),
+            Migration::new("test_1"),
+            Migration::new("test_2"),
+        ];
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .migrate_to("test_0")
+            .unwrap();
+        assert_eq!(plan, [(Step::Down, &local[2]), (Step::Down, &local[1])]);
+    }
+
+    #[test]
+    /// Migrating to a target should refuse to roll back past divergent or irreversible
+    /// migrations, unless they are ignored.
+    fn test_migrate_to_2() {
+        let local = [
+            Migration::new("test_0"),
+            Migration::new("test_1"),
+            Migration::new_with_hash("test_2", "hash"),
+        ];
+        let db = [
+            Migration::new("test_0"),
+            Migration::new("test_1"),
+            Migration::new_with_hash("test_2", "hash"),
+            Migration::new("test_3"),
+        ];
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .migrate_to("test_0");
+        assert!(matches!(plan, Err(Error::DivergentMigration)));
+
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .set_ignore_divergent(true)
+            .migrate_to("test_0");
+        assert!(matches!(plan, Err(Error::UnrollbackableMigration)));
+
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .set_ignore_divergent(true)
+            .set_ignore_unreversable(true)
+            .migrate_to("test_0")
+            .unwrap();
+        assert_eq!(plan, [(Step::Down, &local[1])]);
+
+        let plan = PlanBuilder::new()
+            .local_migrations(&local)
+            .db_migrations(&db)
+            .migrate_to("test_4");
+        assert!(matches!(plan, Err(Error::MigrationNotFound(_))));
+    }
+
     #[test]
     /// Fix should rollback all variant and divergent migrations, and then run pending migrations.
     fn test_fix_1() {
@@ -0,0 +1,207 @@
+use crate::migration::{self, Migration};
+use crate::sql::split_statements;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// The `[policy]` section of `movine.toml`: rules every migration must follow, checked by
+/// `generate`, `check` and `up`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Only migrations from this version on are held to `require_down`, `require_description`
+    /// and `max_file_size`, so that they can be adopted without editing applied migrations.
+    pub since: Option<String>,
+    /// Migrations must have a down migration with at least one statement.
+    pub require_down: bool,
+    /// Migrations must have a `-- +movine description <text>` directive in their up migration.
+    pub require_description: bool,
+    /// Neither the up nor the down migration may be larger than this many bytes.
+    pub max_file_size: Option<usize>,
+    /// Applied migrations must not be changed.
+    pub forbid_editing_applied: bool,
+    /// Pending migrations must be newer than every applied migration.
+    pub forbid_out_of_order: bool,
+}
+
+/// A migration breaking one of the rules of the `Policy`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub migration: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.migration, self.reason)
+    }
+}
+
+impl Policy {
+    /// Checks the local migrations against the policy, given the ones applied to the database.
+    pub fn check(
+        &self,
+        local_migrations: &[Migration],
+        db_migrations: &[Migration],
+    ) -> Vec<Violation> {
+        let mut local_migrations: Vec<_> = local_migrations.iter().collect();
+        local_migrations.sort_by(|a, b| migration::compare_names(&a.name, &b.name));
+        let latest_applied = db_migrations
+            .iter()
+            .map(|m| m.name.as_str())
+            .max_by(|a, b| migration::compare_names(a, b));
+
+        let mut violations = Vec::new();
+        for local in local_migrations {
+            violations.extend(self.check_migration(local));
+            let applied = db_migrations.iter().find(|m| m.name == local.name);
+            match (applied, latest_applied) {
+                (Some(applied), _) if self.forbid_editing_applied && applied.hash != local.hash => {
+                    violations.push(violation(
+                        local,
+                        "was edited after it was applied".to_owned(),
+                    ));
+                }
+                (None, Some(latest))
+                    if self.forbid_out_of_order
+                        && migration::compare_names(&local.name, latest) == Ordering::Less =>
+                {
+                    violations.push(violation(
+                        local,
+                        format!("is older than the applied migration `{}`", latest),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        violations
+    }
+
+    /// Checks the rules that only depend on the migration itself.
+    fn check_migration(&self, migration: &Migration) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let is_init = migration.name.ends_with("_movine_init");
+        let covered = match &self.since {
+            Some(since) => migration::compare_names(&migration.name, since) != Ordering::Less,
+            None => true,
+        };
+        if is_init || !covered {
+            return violations;
+        }
+
+        let has_down =
+            matches!(&migration.down_sql, Some(sql) if !split_statements(sql).is_empty());
+        if self.require_down && !has_down {
+            violations.push(violation(migration, "has no down migration".to_owned()));
+        }
+        let has_description = migration
+            .up_sql
+            .iter()
+            .flat_map(|sql| migration::directives(sql))
+            .any(|(name, value)| name == "description" && !value.is_empty());
+        if self.require_description && !has_description {
+            violations.push(violation(
+                migration,
+                "has no `-- +movine description` in its up migration".to_owned(),
+            ));
+        }
+        if let Some(max) = self.max_file_size {
+            for (step, sql) in &[("up", &migration.up_sql), ("down", &migration.down_sql)] {
+                let size = sql.as_deref().map_or(0, str::len);
+                if size > max {
+                    violations.push(violation(
+                        migration,
+                        format!(
+                            "its {} migration is {} bytes, over the limit of {}",
+                            step, size, max
+                        ),
+                    ));
+                }
+            }
+        }
+        violations
+    }
+}
+
+fn violation(migration: &Migration, reason: String) -> Violation {
+    Violation {
+        migration: migration.name.to_owned(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::MigrationBuilder;
+
+    fn migration(name: &str, up_sql: &str, down_sql: Option<&str>) -> Migration {
+        let mut builder = MigrationBuilder::new();
+        builder.compound_name(name).up_sql(up_sql);
+        if let Some(down_sql) = down_sql {
+            builder.down_sql(down_sql);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    /// Every broken rule should be reported, each against the migration breaking it.
+    fn test_policy_violations() {
+        let policy = Policy {
+            since: Some("0002".to_owned()),
+            require_down: true,
+            require_description: true,
+            max_file_size: Some(40),
+            forbid_editing_applied: true,
+            forbid_out_of_order: true,
+        };
+        let local = vec![
+            migration(
+                "0000_movine_init",
+                "CREATE TABLE movine_migrations ();",
+                None,
+            ),
+            migration("0001_old", "CREATE TABLE a ();", None),
+            migration(
+                "0002_edited",
+                "-- +movine description b\nCREATE TABLE b (x int);",
+                Some("DROP TABLE b;"),
+            ),
+            migration(
+                "0003_late",
+                "-- +movine description c\nSELECT 1;",
+                Some("SELECT 1;"),
+            ),
+            migration("0005_new", "CREATE TABLE d ();", Some("-- nothing\n")),
+        ];
+        let db = vec![
+            migration(
+                "0000_movine_init",
+                "CREATE TABLE movine_migrations ();",
+                None,
+            ),
+            migration(
+                "0002_edited",
+                "-- +movine description b\nCREATE TABLE b ();",
+                Some("DROP TABLE b;"),
+            ),
+            migration("0004_other", "SELECT 1;", None),
+        ];
+        let violations: Vec<_> = policy
+            .check(&local, &db)
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "0001_old: is older than the applied migration `0004_other`",
+                "0002_edited: its up migration is 48 bytes, over the limit of 40",
+                "0002_edited: was edited after it was applied",
+                "0003_late: is older than the applied migration `0004_other`",
+                "0005_new: has no down migration",
+                "0005_new: has no `-- +movine description` in its up migration",
+            ]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use crate::errors::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A snapshot of the objects in a database, as produced by `DbAdaptor::dump_schema`. Movine's
+/// own `movine_migrations` table is never included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub objects: Vec<SchemaObject>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    pub kind: ObjectKind,
+    pub name: String,
+    /// The DDL statement creating the object, including the trailing `;`.
+    pub definition: String,
+}
+
+/// Kinds of schema objects, in the order they are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectKind {
+    Type,
+    Sequence,
+    Table,
+    Index,
+    View,
+    Function,
+    Trigger,
+}
+
+impl ObjectKind {
+    const ALL: [ObjectKind; 7] = [
+        ObjectKind::Type,
+        ObjectKind::Sequence,
+        ObjectKind::Table,
+        ObjectKind::Index,
+        ObjectKind::View,
+        ObjectKind::Function,
+        ObjectKind::Trigger,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ObjectKind::Type => "type",
+            ObjectKind::Sequence => "sequence",
+            ObjectKind::Table => "table",
+            ObjectKind::Index => "index",
+            ObjectKind::View => "view",
+            ObjectKind::Function => "function",
+            ObjectKind::Trigger => "trigger",
+        }
+    }
+}
+
+impl Schema {
+    pub fn new(mut objects: Vec<SchemaObject>) -> Self {
+        objects.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+        Self { objects }
+    }
+
+    /// Parses a snapshot written by `dump-schema` back into a schema. Each object starts at its
+    /// `-- <kind>: <name>` marker line; anything before the first marker is ignored.
+    pub fn parse(snapshot: &str) -> Result<Self> {
+        let mut objects = Vec::new();
+        let mut current: Option<SchemaObject> = None;
+        for line in snapshot.lines() {
+            if let Some(object) = parse_marker(line) {
+                objects.extend(current.replace(object));
+            } else if let Some(object) = current.as_mut() {
+                object.definition.push_str(line);
+                object.definition.push('\n');
+            } else if !line.trim().is_empty() && !line.starts_with("--") {
+                return Err(Error::InvalidSchema(format!(
+                    "expected a `-- <kind>: <name>` line, found `{}`",
+                    line
+                )));
+            }
+        }
+        objects.extend(current);
+        for object in &mut objects {
+            object.definition = object.definition.trim().to_owned();
+        }
+        Ok(Self::new(objects))
+    }
+}
+
+impl Schema {
+    /// The objects in an order they can be created in: types, sequences and functions first,
+    /// then tables, indexes, views and triggers. Tables and views come after the tables and
+    /// views their definitions mention, as far as that's possible without a cycle.
+    pub fn creation_order(&self) -> Vec<&SchemaObject> {
+        let rank = |kind| match kind {
+            ObjectKind::Type => 0,
+            ObjectKind::Sequence => 1,
+            ObjectKind::Function => 2,
+            ObjectKind::Table => 3,
+            ObjectKind::Index => 4,
+            ObjectKind::View => 5,
+            ObjectKind::Trigger => 6,
+        };
+        let mut remaining: Vec<&SchemaObject> = self.objects.iter().collect();
+        remaining.sort_by_key(|o| rank(o.kind));
+
+        let mut ordered: Vec<&SchemaObject> = Vec::new();
+        while !remaining.is_empty() {
+            let is_ready = |object: &SchemaObject| {
+                !remaining.iter().any(|other| {
+                    other.name != object.name
+                        && matches!(other.kind, ObjectKind::Table | ObjectKind::View)
+                        && matches!(object.kind, ObjectKind::Table | ObjectKind::View)
+                        && mentions(&object.definition, &other.name)
+                })
+            };
+            let next = remaining.iter().position(|o| is_ready(o)).unwrap_or(0);
+            ordered.push(remaining.remove(next));
+        }
+        ordered
+    }
+}
+
+/// Whether `name` appears in `sql` as a whole identifier.
+fn mentions(sql: &str, name: &str) -> bool {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    sql.match_indices(name).any(|(i, _)| {
+        let before = sql[..i].chars().next_back();
+        let after = sql[i + name.len()..].chars().next();
+        !matches!(before, Some(c) if is_identifier(c))
+            && !matches!(after, Some(c) if is_identifier(c))
+    })
+}
+
+fn parse_marker(line: &str) -> Option<SchemaObject> {
+    let (kind, name) = line.strip_prefix("-- ")?.split_once(": ")?;
+    let kind = ObjectKind::ALL.iter().find(|k| k.as_str() == kind)?;
+    Some(SchemaObject {
+        kind: *kind,
+        name: name.to_owned(),
+        definition: String::new(),
+    })
+}
+
+/// Renders the schema as SQL, one statement per object, sorted by kind and then by name so
+/// that dumps of identical databases are byte-for-byte identical. Each statement is preceded by
+/// a `-- <kind>: <name>` line so that the snapshot can be read back by `Schema::parse`.
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "-- Generated by `movine dump-schema`. Do not edit.")?;
+        for object in &self.objects {
+            writeln!(f)?;
+            writeln!(f, "-- {}: {}", object.kind.as_str(), object.name)?;
+            writeln!(f, "{}", object.definition.trim())?;
+        }
+        Ok(())
+    }
+}
+
+/// A difference between the expected schema and the one found in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// In the database, but not in the expected schema.
+    Added { object: String, definition: String },
+    /// In the expected schema, but not in the database.
+    Removed { object: String, definition: String },
+    Changed {
+        object: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Lists the differences between `expected` and `actual`. Tables are compared column by column
+/// and constraint by constraint; other objects as a whole.
+pub fn diff(expected: &Schema, actual: &Schema) -> Vec<Difference> {
+    let key = |object: &SchemaObject| (object.kind, object.name.to_owned());
+    let expected: BTreeMap<_, _> = expected.objects.iter().map(|o| (key(o), o)).collect();
+    let actual: BTreeMap<_, _> = actual.objects.iter().map(|o| (key(o), o)).collect();
+
+    let mut differences = Vec::new();
+    for (key, expected_object) in &expected {
+        let description = format!("{} `{}`", key.0.as_str(), key.1);
+        match actual.get(key) {
+            None => differences.push(Difference::Removed {
+                object: description,
+                definition: expected_object.definition.to_owned(),
+            }),
+            Some(actual_object) if actual_object.definition == expected_object.definition => {}
+            Some(actual_object) => {
+                let table_differences = match key.0 {
+                    ObjectKind::Table => diff_tables(&key.1, expected_object, actual_object),
+                    _ => None,
+                };
+                differences.extend(table_differences.unwrap_or_else(|| {
+                    vec![Difference::Changed {
+                        object: description,
+                        expected: expected_object.definition.to_owned(),
+                        actual: actual_object.definition.to_owned(),
+                    }]
+                }));
+            }
+        }
+    }
+    for (key, actual_object) in &actual {
+        if !expected.contains_key(key) {
+            differences.push(Difference::Added {
+                object: format!("{} `{}`", key.0.as_str(), key.1),
+                definition: actual_object.definition.to_owned(),
+            });
+        }
+    }
+    differences
+}
+
+/// Compares two `CREATE TABLE` statements item by item. Returns `None` if either can't be split
+/// into columns and constraints.
+fn diff_tables(
+    table: &str,
+    expected: &SchemaObject,
+    actual: &SchemaObject,
+) -> Option<Vec<Difference>> {
+    let expected = table_items(&expected.definition)?;
+    let actual = table_items(&actual.definition)?;
+
+    let mut differences = Vec::new();
+    for (name, expected_definition) in &expected {
+        let object = format!("{} on table `{}`", name, table);
+        match actual.get(name) {
+            None => differences.push(Difference::Removed {
+                object,
+                definition: expected_definition.to_owned(),
+            }),
+            Some(actual_definition) if actual_definition == expected_definition => {}
+            Some(actual_definition) => differences.push(Difference::Changed {
+                object,
+                expected: expected_definition.to_owned(),
+                actual: actual_definition.to_owned(),
+            }),
+        }
+    }
+    for (name, actual_definition) in &actual {
+        if !expected.contains_key(name) {
+            differences.push(Difference::Added {
+                object: format!("{} on table `{}`", name, table),
+                definition: actual_definition.to_owned(),
+            });
+        }
+    }
+    Some(differences)
+}
+
+/// Splits the body of a `CREATE TABLE` statement into its columns and constraints, keyed by a
+/// description such as ``column `id` `` or ``constraint `users_pkey` ``.
+fn table_items(definition: &str) -> Option<BTreeMap<String, String>> {
+    let body = &definition[definition.find('(')? + 1..definition.rfind(')')?];
+    let mut items = BTreeMap::new();
+    for item in split_top_level(body) {
+        let first_word = item.split_whitespace().next()?.to_ascii_uppercase();
+        let key = match first_word.as_str() {
+            "CONSTRAINT" => {
+                let name = item.split_whitespace().nth(1)?;
+                format!("constraint `{}`", name)
+            }
+            "PRIMARY" | "UNIQUE" | "CHECK" | "FOREIGN" | "EXCLUDE" => {
+                format!("constraint `{}`", item)
+            }
+            _ => format!("column `{}`", column_name(item)),
+        };
+        items.insert(key, item.to_owned());
+    }
+    Some(items)
+}
+
+fn column_name(item: &str) -> &str {
+    if let Some(quoted) = item.strip_prefix('"') {
+        let end = quoted.find('"').map_or(item.len(), |i| i + 2);
+        &item[..end]
+    } else {
+        item.split_whitespace().next().unwrap_or(item)
+    }
+}
+
+/// Splits on commas that aren't inside parentheses or quotes.
+fn split_top_level(body: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                items.push(body[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(body[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Formats a `CREATE TABLE` statement from its column and constraint definitions.
+pub fn create_table(name: &str, columns: &[String], constraints: &[String]) -> String {
+    let body: Vec<String> = columns
+        .iter()
+        .chain(constraints)
+        .map(|line| format!("    {}", line))
+        .collect();
+    format!("CREATE TABLE {} (\n{}\n);", name, body.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Objects should be rendered by kind, then by name, whatever order they were found in.
+    fn test_render_sorted() {
+        let object = |kind, name: &str| SchemaObject {
+            kind,
+            name: name.to_owned(),
+            definition: format!("-- {}", name),
+        };
+        let schema = Schema::new(vec![
+            object(ObjectKind::Index, "a_idx"),
+            object(ObjectKind::Table, "b"),
+            object(ObjectKind::Table, "a"),
+        ]);
+        assert_eq!(
+            schema.to_string(),
+            "-- Generated by `movine dump-schema`. Do not edit.\n\n-- table: a\n-- a\n\n\
+             -- table: b\n-- b\n\n-- index: a_idx\n-- a_idx\n"
+        );
+        assert_eq!(Schema::parse(&schema.to_string()).unwrap(), schema);
+    }
+
+    #[test]
+    /// Tables should be created after the tables they reference.
+    fn test_creation_order() {
+        let object = |kind, name: &str, definition: &str| SchemaObject {
+            kind,
+            name: name.to_owned(),
+            definition: definition.to_owned(),
+        };
+        let schema = Schema::new(vec![
+            object(ObjectKind::Index, "a_idx", "CREATE INDEX a_idx ON a (id);"),
+            object(
+                ObjectKind::Table,
+                "a",
+                "CREATE TABLE a (id int REFERENCES b(id));",
+            ),
+            object(ObjectKind::Table, "b", "CREATE TABLE b (id int, b_id int);"),
+            object(ObjectKind::Function, "f()", "CREATE FUNCTION f() ..."),
+        ]);
+        let names: Vec<_> = schema
+            .creation_order()
+            .iter()
+            .map(|o| o.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["f()", "b", "a", "a_idx"]);
+    }
+
+    #[test]
+    /// Table differences should be reported per column and constraint.
+    fn test_diff_tables() {
+        let table = |definition: &str| {
+            Schema::new(vec![SchemaObject {
+                kind: ObjectKind::Table,
+                name: "users".to_owned(),
+                definition: definition.to_owned(),
+            }])
+        };
+        let expected = table("CREATE TABLE users (\n    id integer,\n    name text DEFAULT 'a,b',\n    CONSTRAINT users_pkey PRIMARY KEY (id)\n);");
+        let actual = table("CREATE TABLE users (\n    id bigint,\n    name text DEFAULT 'a,b',\n    email text\n);");
+        assert_eq!(
+            diff(&expected, &actual),
+            vec![
+                Difference::Changed {
+                    object: "column `id` on table `users`".to_owned(),
+                    expected: "id integer".to_owned(),
+                    actual: "id bigint".to_owned(),
+                },
+                Difference::Removed {
+                    object: "constraint `users_pkey` on table `users`".to_owned(),
+                    definition: "CONSTRAINT users_pkey PRIMARY KEY (id)".to_owned(),
+                },
+                Difference::Added {
+                    object: "column `email` on table `users`".to_owned(),
+                    definition: "email text".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::adaptor::DbAdaptor;
+use crate::config::Config;
+use crate::errors::{Error, Result};
+use postgres::error::SqlState;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::SystemTime;
+
+/// A throwaway database of the same kind as the configured one, for running the local
+/// migrations without touching the real database. SQLite scratch databases live in memory. On
+/// Postgres a `movine_scratch_<random suffix>` database is created on the same server, which
+/// needs the `CREATEDB` privilege, and dropped again when the `ScratchDatabase` is dropped. Only
+/// a database this process created is ever dropped, so concurrent runs against a shared server
+/// (e.g. CI jobs that all run as pid 1) don't interfere.
+///
+/// `shadow` creates the same kind of database, named `movine_shadow_<random suffix>` on
+/// Postgres, for checking migrations before they are run on the real database.
+pub struct ScratchDatabase {
+    adaptor: Option<Box<dyn DbAdaptor>>,
+    postgres: Option<(postgres::Client, String)>,
+}
+
+impl ScratchDatabase {
+    pub fn create(config: &Config) -> Result<Self> {
+        Self::create_with_prefix(config, "movine_scratch")
+    }
+
+    pub fn shadow(config: &Config) -> Result<Self> {
+        Self::create_with_prefix(config, "movine_shadow")
+    }
+
+    fn create_with_prefix(config: &Config, prefix: &str) -> Result<Self> {
+        match config {
+            Config {
+                database_url: Some(_),
+                ..
+            }
+            | Config {
+                postgres: Some(_), ..
+            } => Self::create_postgres(config, prefix),
+            Config {
+                sqlite: Some(_), ..
+            } => Ok(Self {
+                adaptor: Some(Box::new(rusqlite::Connection::open_in_memory()?)),
+                postgres: None,
+            }),
+            _ => Err(Error::AdaptorNotFound),
+        }
+    }
+
+    fn create_postgres(config: &Config, prefix: &str) -> Result<Self> {
+        let mut client = match config.database_url {
+            Some(_) => config.clone().into_pg_conn_from_url()?,
+            None => config.clone().into_pg_conn_from_config()?,
+        };
+        let mut attempt = 1;
+        let name = loop {
+            let name = format!("{}_{}", prefix, unique_suffix());
+            debug!("Creating scratch database {}", name);
+            match client.batch_execute(&format!("CREATE DATABASE {}", name)) {
+                Ok(()) => break name,
+                // Someone else's database; never touch it, just pick another name.
+                Err(e)
+                    if e.code() == Some(&SqlState::DUPLICATE_DATABASE)
+                        && attempt < CREATE_ATTEMPTS =>
+                {
+                    attempt += 1
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        // Once `postgres` is set, dropping `Self` cleans up even if connecting fails.
+        let mut scratch = Self {
+            adaptor: None,
+            postgres: Some((client, name.to_owned())),
+        };
+        scratch.adaptor = Some(config.with_database(&name).into_db_adaptor()?);
+        Ok(scratch)
+    }
+
+    pub fn adaptor(&mut self) -> &mut dyn DbAdaptor {
+        self.adaptor
+            .as_mut()
+            .expect("scratch database is connected until dropped")
+            .as_mut()
+    }
+}
+
+const CREATE_ATTEMPTS: u32 = 3;
+
+/// A random suffix for scratch database names, so that concurrent runs never pick the same one.
+fn unique_suffix() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        // Postgres refuses to drop a database that still has connections.
+        self.adaptor.take();
+        if let Some((client, name)) = &mut self.postgres {
+            debug!("Dropping scratch database {}", name);
+            if let Err(e) = client.batch_execute(&format!("DROP DATABASE IF EXISTS {}", name)) {
+                warn!("Unable to drop scratch database {}: {}", name, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Scratch database names should differ between runs, even within one process.
+    fn test_unique_suffix() {
+        let suffix = unique_suffix();
+        assert_eq!(suffix.len(), 16);
+        assert!(suffix
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert_ne!(unique_suffix(), suffix);
+    }
+}
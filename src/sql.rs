@@ -0,0 +1,319 @@
+use crate::errors::{Error, Result, SqlLocation};
+use crate::migration::Migration;
+use crate::plan_builder::Step;
+
+/// A single statement split out of a migration.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Statement<'a> {
+    /// The statement from its first token up to and including the terminating `;`, if any.
+    pub sql: &'a str,
+    /// Byte offset of the statement in the SQL it was split from.
+    pub offset: usize,
+    /// 1-based line of the statement's first token.
+    pub line: usize,
+    /// 1-based column of the statement's first token.
+    pub column: usize,
+}
+
+/// Splits `sql` into statements on top-level `;`. Semicolons inside quotes, dollar-quoted
+/// bodies, comments, parentheses and `BEGIN ... END`/`CASE ... END` blocks don't end a
+/// statement. Only the `BEGIN` of a trigger body, `AS BEGIN` and `BEGIN ATOMIC` open a block, so
+/// a transaction's `BEGIN` or a column named `begin` doesn't. Comments between statements are
+/// dropped.
+pub fn split_statements(sql: &str) -> Vec<Statement<'_>> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut blocks = 0usize;
+    let mut parens = 0usize;
+    let mut trigger = false;
+    let mut previous_word = "";
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'-' && bytes.get(i + 1) == Some(&b'-') {
+            i = sql[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+            continue;
+        }
+        if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i = skip_block_comment(bytes, i);
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let statement_start = *start.get_or_insert(i);
+        match c {
+            b'\'' | b'"' | b'`' => i = skip_quoted(bytes, i, false),
+            b'$' => i = skip_dollar_quoted(sql, i),
+            b'(' => {
+                parens += 1;
+                i += 1;
+            }
+            b')' => {
+                parens = parens.saturating_sub(1);
+                i += 1;
+            }
+            b';' if blocks == 0 && parens == 0 => {
+                statements.push(statement(sql, statement_start, i + 1));
+                start = None;
+                trigger = false;
+                previous_word = "";
+                i += 1;
+            }
+            _ if c.is_ascii_alphabetic() || c == b'_' => {
+                let end = word_end(bytes, i);
+                let word = &sql[i..end];
+                if word.eq_ignore_ascii_case("e") && bytes.get(end) == Some(&b'\'') {
+                    // Postgres escape string, e.g. E'it\'s'
+                    i = skip_quoted(bytes, end, true);
+                    continue;
+                }
+                if word.eq_ignore_ascii_case("case")
+                    || (word.eq_ignore_ascii_case("begin")
+                        && opens_block(sql, end, previous_word, trigger && blocks == 0))
+                {
+                    blocks += 1;
+                } else if word.eq_ignore_ascii_case("end") {
+                    blocks = blocks.saturating_sub(1);
+                } else if word.eq_ignore_ascii_case("trigger") {
+                    trigger = true;
+                }
+                previous_word = word;
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    if let Some(start) = start {
+        statements.push(statement(sql, start, sql.len()));
+    }
+    statements
+}
+
+/// Runs the statements of `sql` one at a time with `execute`. If one fails, the error is wrapped
+/// with the migration, the failing statement and where in `sql` it failed.
+pub fn execute_statements<F>(
+    migration: &Migration,
+    step: Step,
+    sql: &str,
+    mut execute: F,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<()>,
+{
+    for statement in split_statements(sql) {
+        debug!(
+            "{} ({:?}) line {}: {}",
+            migration.name, step, statement.line, statement.sql
+        );
+        if let Err(error) = execute(statement.sql) {
+            return Err(Error::MigrationFailed {
+                migration: migration.name.to_owned(),
+                step,
+                location: Some(locate(sql, &statement, error.position())),
+                error: Box::new(error),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Where in `sql` an error occurred: at the 1-based character `position` into `statement` the
+/// database reported, or at the start of the statement if it didn't report one.
+fn locate(sql: &str, statement: &Statement, position: Option<usize>) -> SqlLocation {
+    let (line, column) = position
+        .and_then(|position| {
+            let (index, _) = statement.sql.char_indices().nth(position.checked_sub(1)?)?;
+            Some(line_column(sql, statement.offset + index))
+        })
+        .unwrap_or((statement.line, statement.column));
+    SqlLocation {
+        statement: statement.sql.to_owned(),
+        line,
+        column,
+    }
+}
+
+fn statement(sql: &str, start: usize, end: usize) -> Statement<'_> {
+    let (line, column) = line_column(sql, start);
+    Statement {
+        sql: sql[start..end].trim_end(),
+        offset: start,
+        line,
+        column,
+    }
+}
+
+/// 1-based line and column of the byte at `offset`.
+fn line_column(sql: &str, offset: usize) -> (usize, usize) {
+    let before = &sql[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+fn word_end(bytes: &[u8], start: usize) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&c| !(c.is_ascii_alphanumeric() || c == b'_' || c == b'$'))
+        .map_or(bytes.len(), |len| start + len)
+}
+
+/// Whether the `BEGIN` ending at `end` starts a block: the body of a trigger, `AS BEGIN` or
+/// `BEGIN ATOMIC`. Anywhere else it is a transaction or just an identifier.
+fn opens_block(sql: &str, end: usize, previous_word: &str, trigger_body: bool) -> bool {
+    let rest = sql[end..].trim_start();
+    let next = &rest[..word_end(rest.as_bytes(), 0)];
+    trigger_body || previous_word.eq_ignore_ascii_case("as") || next.eq_ignore_ascii_case("atomic")
+}
+
+/// Skips a quoted string or identifier starting at `start`, where a doubled quote is an escaped
+/// quote. Returns the offset just past the closing quote.
+fn skip_quoted(bytes: &[u8], start: usize, backslash_escapes: bool) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        let escaped = (backslash_escapes && bytes[i] == b'\\')
+            || (bytes[i] == quote && bytes.get(i + 1) == Some(&quote));
+        if escaped {
+            i += 2;
+        } else if bytes[i] == quote {
+            return i + 1;
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// Skips a Postgres dollar-quoted string such as `$$ ... $$` or `$body$ ... $body$`. A `$`
+/// that doesn't open one (e.g. the parameter `$1`) is skipped on its own.
+fn skip_dollar_quoted(sql: &str, start: usize) -> usize {
+    let bytes = sql.as_bytes();
+    let tag_end = bytes[start + 1..]
+        .iter()
+        .position(|&c| !(c.is_ascii_alphanumeric() || c == b'_'))
+        .map_or(bytes.len(), |len| start + 1 + len);
+    let is_tag = bytes.get(tag_end) == Some(&b'$')
+        && !matches!(bytes.get(start + 1), Some(c) if c.is_ascii_digit());
+    if !is_tag {
+        return start + 1;
+    }
+    let delimiter = &sql[start..=tag_end];
+    sql[tag_end + 1..]
+        .find(delimiter)
+        .map_or(bytes.len(), |i| tag_end + 1 + i + delimiter.len())
+}
+
+/// Skips a (possibly nested) `/* ... */` comment starting at `start`.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            depth += 1;
+            i += 2;
+        } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(sql: &str) -> Vec<&str> {
+        split_statements(sql).iter().map(|s| s.sql).collect()
+    }
+
+    #[test]
+    /// Semicolons inside strings, comments and bodies shouldn't split statements.
+    fn test_split_statements() {
+        assert_eq!(
+            split("SELECT 'a;b', \"c;d\"; -- e;f\nSELECT E'it\\'s;' /* g; /* h; */ */;"),
+            vec![
+                "SELECT 'a;b', \"c;d\";",
+                "SELECT E'it\\'s;' /* g; /* h; */ */;"
+            ]
+        );
+        assert_eq!(
+            split("CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql;\nSELECT $1"),
+            vec![
+                "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql;",
+                "SELECT $1"
+            ]
+        );
+        assert_eq!(
+            split("CREATE TRIGGER t AFTER INSERT ON a BEGIN\n  UPDATE a SET x = CASE WHEN 1 THEN 2 END;\nEND;\nBEGIN; END;"),
+            vec![
+                "CREATE TRIGGER t AFTER INSERT ON a BEGIN\n  UPDATE a SET x = CASE WHEN 1 THEN 2 END;\nEND;",
+                "BEGIN;",
+                "END;"
+            ]
+        );
+        assert_eq!(split("-- only a comment\n"), Vec::<&str>::new());
+    }
+
+    #[test]
+    /// Only a trigger body, `AS BEGIN` and `BEGIN ATOMIC` should open a block, not a column
+    /// named `begin` or a transaction.
+    fn test_split_begin() {
+        assert_eq!(
+            split("SELECT begin FROM t;\nBEGIN TRANSACTION;\nSELECT 1;"),
+            vec!["SELECT begin FROM t;", "BEGIN TRANSACTION;", "SELECT 1;"]
+        );
+        assert_eq!(
+            split("CREATE FUNCTION f() RETURNS int LANGUAGE sql BEGIN ATOMIC SELECT 1; END;\nSELECT 2;"),
+            vec![
+                "CREATE FUNCTION f() RETURNS int LANGUAGE sql BEGIN ATOMIC SELECT 1; END;",
+                "SELECT 2;"
+            ]
+        );
+        assert_eq!(
+            split("CREATE PROCEDURE p AS BEGIN SELECT 1; END;\nSELECT 2;"),
+            vec!["CREATE PROCEDURE p AS BEGIN SELECT 1; END;", "SELECT 2;"]
+        );
+        assert_eq!(
+            split("CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT begin FROM b; END;\nSELECT 2;"),
+            vec![
+                "CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT begin FROM b; END;",
+                "SELECT 2;"
+            ]
+        );
+    }
+
+    #[test]
+    /// Errors should point at the statement and line/column they occurred in.
+    fn test_locate() {
+        let sql = "CREATE TABLE a (id INT);\nCREATE TABLE b (\n    id INTEGR\n);\n";
+        let statements = split_statements(sql);
+        let position = statements[1].sql.find("INTEGR").unwrap() + 1;
+        let location = locate(sql, &statements[1], Some(position));
+        assert_eq!(location.line, 3);
+        assert_eq!(location.column, 8);
+        assert_eq!(location.statement, "CREATE TABLE b (\n    id INTEGR\n);");
+        let location = locate(sql, &statements[1], None);
+        assert_eq!((location.line, location.column), (2, 1));
+    }
+
+    #[test]
+    /// Statements should know the line and column they start on.
+    fn test_statement_positions() {
+        let statements = split_statements("-- +movine lock_timeout 5s\nSELECT 1;\n\n  SELECT\n 2;");
+        let positions: Vec<_> = statements.iter().map(|s| (s.line, s.column)).collect();
+        assert_eq!(positions, vec![(2, 1), (4, 3)]);
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+    }
+}
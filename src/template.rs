@@ -0,0 +1,123 @@
+use crate::errors::{Error, Result};
+use chrono::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A named set of `up.sql`/`down.sql` files used as the starting point for new migrations.
+///
+/// Templates live in `<templates_dir>/<name>/` and may contain the placeholders `{{name}}`,
+/// `{{timestamp}}` and `{{author}}`.
+pub struct Template {
+    up_sql: String,
+    down_sql: String,
+}
+
+impl Template {
+    pub fn load(templates_dir: &str, name: &str) -> Result<Self> {
+        let folder: PathBuf = [templates_dir, name].iter().collect();
+        if !folder.is_dir() {
+            return Err(Error::TemplateNotFound(name.to_owned()));
+        }
+
+        Ok(Self {
+            up_sql: read_optional(&folder.join("up.sql"))?,
+            down_sql: read_optional(&folder.join("down.sql"))?,
+        })
+    }
+
+    pub fn render(&self, name: &str, date: DateTime<Utc>) -> (String, String) {
+        self.render_with(name, date, author)
+    }
+
+    /// Renders the template, only calling `author` if the template uses `{{author}}`.
+    fn render_with<F>(&self, name: &str, date: DateTime<Utc>, author: F) -> (String, String)
+    where
+        F: FnOnce() -> String,
+    {
+        let timestamp = date.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+        let uses_author = [&self.up_sql, &self.down_sql]
+            .iter()
+            .any(|sql| sql.contains("{{author}}"));
+        let author = if uses_author { author() } else { String::new() };
+        let render = |sql: &str| {
+            sql.replace("{{name}}", name)
+                .replace("{{timestamp}}", &timestamp)
+                .replace("{{author}}", &author)
+        };
+        (render(&self.up_sql), render(&self.down_sql))
+    }
+}
+
+fn read_optional(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(sql) => Ok(sql),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The author is taken from `MOVINE_AUTHOR`, then git's `user.name`, then the login name.
+fn author() -> String {
+    if let Ok(author) = std::env::var("MOVINE_AUTHOR") {
+        return author;
+    }
+    if let Ok(output) = Command::new("git").arg("config").arg("user.name").output() {
+        if output.status.success() {
+            return String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        }
+    }
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Placeholders should be filled in, and a missing `down.sql` should render as empty.
+    fn test_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("table");
+        fs::create_dir(&folder).unwrap();
+        fs::write(
+            folder.join("up.sql"),
+            "-- {{name}} by {{author}} at {{timestamp}}\nCREATE TABLE {{name}} ();\n",
+        )
+        .unwrap();
+        let templates_dir = dir.path().to_str().unwrap();
+        let template = Template::load(templates_dir, "table").unwrap();
+        let date = Utc.timestamp_opt(1552840491, 0).unwrap();
+        let (up_sql, down_sql) = template.render_with("users", date, || "Ada".to_owned());
+        assert_eq!(
+            up_sql,
+            "-- users by Ada at 2019-03-17 16:34:51 UTC\nCREATE TABLE users ();\n"
+        );
+        assert_eq!(down_sql, "");
+
+        assert!(matches!(
+            Template::load(templates_dir, "missing"),
+            Err(Error::TemplateNotFound(_))
+        ));
+    }
+
+    #[test]
+    /// The author should only be looked up when the template uses it.
+    fn test_render_without_author() {
+        let template = Template {
+            up_sql: "CREATE TABLE {{name}} ();".to_owned(),
+            down_sql: "DROP TABLE {{name}};".to_owned(),
+        };
+        let date = Utc.timestamp_opt(0, 0).unwrap();
+        let rendered = template.render_with("users", date, || panic!("author looked up"));
+        assert_eq!(
+            rendered,
+            (
+                "CREATE TABLE users ();".to_owned(),
+                "DROP TABLE users;".to_owned()
+            )
+        );
+    }
+}